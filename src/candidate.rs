@@ -0,0 +1,240 @@
+use crate::{is_square, Verdict};
+use std::fmt::{Display, Formatter, Result};
+
+// The patterns are from figure 5 of http://www.multimagie.com/Search.pdf#page=2
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pattern { Pattern1, Pattern2, Pattern3, Pattern4, Pattern5, Pattern6, Unclassified, SemiMagic }
+
+// A 3x3 grid reported by a pattern checker. The cells are stored row by row
+// and hold the values in the magic square, i.e. the squares, not their roots.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MagicSquareCandidate {
+    pub pattern: Pattern,
+    pub center: u64,
+    pub cells: [u128; 9],
+    pub squares: [bool; 9],
+    pub factors: Vec<u64>,
+    pub scale: u128,
+    pub scaled_copy: bool,
+    pub known: Option<String>,
+    pub verdict: Verdict,
+}
+
+impl Pattern {
+    pub fn number(&self) -> u8 {
+        match self {
+            Pattern::Pattern1 => 1,
+            Pattern::Pattern2 => 2,
+            Pattern::Pattern3 => 3,
+            Pattern::Pattern4 => 4,
+            Pattern::Pattern5 => 5,
+            Pattern::Pattern6 => 6,
+            // A grid with more than seven squares, which fits several patterns.
+            Pattern::Unclassified => 0,
+            // Not one of the figure 5 patterns. The verdict tells them apart.
+            Pattern::SemiMagic => 0,
+        }
+    }
+
+    // The pattern whose non-square cells are the two false cells, if there
+    // are exactly two of them and neither is the center.
+    pub fn classify(squares: &[bool; 9]) -> Option<Pattern> {
+        let non_squares = (0..9).filter(|&i| !squares[i]).collect::<Vec<_>>();
+        let &[i, j] = non_squares.as_slice() else { return None };
+        if i == 4 || j == 4 { return None; }
+
+        let is_corner = |k: usize| k.is_multiple_of(2);
+        let opposite = i + j == 8;
+        let same_line = i / 3 == j / 3 || i % 3 == j % 3;
+
+        Some(match (is_corner(i), is_corner(j)) {
+            (false, false) if opposite => Pattern::Pattern1,
+            (false, false) => Pattern::Pattern2,
+            (true, true) if opposite => Pattern::Pattern6,
+            (true, true) => Pattern::Pattern5,
+            _ if same_line => Pattern::Pattern3,
+            _ => Pattern::Pattern4,
+        })
+    }
+}
+
+// The eight rotations and reflections of a grid as the cell each new cell is
+// taken from. They preserve every line so a candidate stays magic under them.
+const SYMMETRIES: [[usize; 9]; 8] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8], [6, 3, 0, 7, 4, 1, 8, 5, 2], // identity, quarter turn
+    [8, 7, 6, 5, 4, 3, 2, 1, 0], [2, 5, 8, 1, 4, 7, 0, 3, 6], // half turn, three quarter turn
+    [2, 1, 0, 5, 4, 3, 8, 7, 6], [6, 7, 8, 3, 4, 5, 0, 1, 2], // mirror left to right, top to bottom
+    [0, 3, 6, 1, 4, 7, 2, 5, 8], [8, 5, 2, 7, 4, 1, 6, 3, 0], // transpose, anti-transpose
+];
+
+// The smallest of the eight rotations and reflections of a grid, so that
+// candidates found in different orientations can be compared.
+pub fn canonical_orientation(cells: [u128; 9]) -> [u128; 9] {
+    canonical_symmetry(&cells).map(|i| cells[i])
+}
+
+// The cells divided by their greatest common divisor, in the canonical
+// orientation. A square found again at a multiple of its center has the same
+// primitive cells.
+pub fn primitive_orientation(cells: [u128; 9]) -> [u128; 9] {
+    let divisor = cells.iter().fold(0, |divisor, &cell| gcd(divisor, cell));
+    canonical_orientation(cells.map(|cell| cell / divisor))
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from('"');
+
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => { json.push('\\'); json.push(c); },
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+pub fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 { (a, b) = (b, a % b); }
+    a
+}
+
+fn canonical_symmetry(cells: &[u128; 9]) -> [usize; 9] {
+    *SYMMETRIES.iter().min_by_key(|symmetry| symmetry.map(|i| cells[i])).unwrap()
+}
+
+impl MagicSquareCandidate {
+    pub fn new(pattern: Pattern, center: u64, cells: [u128; 9]) -> Self {
+        // A zero marks a cell that the checker did not compute.
+        let squares = cells.map(|cell| cell != 0 && is_square(cell));
+
+        let scale = cells.iter().fold(0, |divisor, &cell| gcd(divisor, cell));
+
+        Self { pattern, center, cells, squares, factors: vec![], scale, scaled_copy: false, known: None, verdict: Verdict::Unverified }
+    }
+
+    // Turn the grid into its canonical orientation so that every checker
+    // reports the same square the same way.
+    pub fn canonicalize(&mut self) {
+        let symmetry = canonical_symmetry(&self.cells);
+        self.cells = symmetry.map(|i| self.cells[i]);
+        self.squares = symmetry.map(|i| self.squares[i]);
+    }
+
+    pub fn primitive_cells(&self) -> [u128; 9] {
+        primitive_orientation(self.cells)
+    }
+
+    pub fn to_json(&self) -> String {
+        let join = |values: Vec<String>| values.join(",");
+
+        format!(
+            r#"{{"pattern":{},"center":{},"cells":[{}],"squares":[{}],"factors":[{}],"scale":{},"scaled_copy":{},"known":{},"verdict":"{}"}}"#,
+            self.pattern.number(),
+            self.center,
+            join(self.cells.iter().map(|c| c.to_string()).collect()),
+            join(self.squares.iter().map(|s| s.to_string()).collect()),
+            join(self.factors.iter().map(|f| f.to_string()).collect()),
+            self.scale,
+            self.scaled_copy,
+            self.known.as_deref().map_or("null".to_string(), json_string),
+            self.verdict.name(),
+        )
+    }
+}
+
+impl Display for MagicSquareCandidate {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let [top_left, top_middle, top_right, middle_left, middle_middle, middle_right, bottom_left, bottom_middle, bottom_right] = self.cells;
+
+        writeln!(f, "Pattern {} at center {} ({}):", self.pattern.number(), self.center, self.verdict.name())?;
+        if let Some(note) = &self.known { writeln!(f, "Known solution: {note}")?; }
+        writeln!(f, "----------------------------------------------------------------------------------------------------")?;
+        writeln!(f, "| {top_left:^30} | {top_middle:^30} | {top_right:^30} |")?;
+        writeln!(f, "|--------------------------------------------------------------------------------------------------|")?;
+        writeln!(f, "| {middle_left:^30} | {middle_middle:^30} | {middle_right:^30} |")?;
+        writeln!(f, "|--------------------------------------------------------------------------------------------------|")?;
+        writeln!(f, "| {bottom_left:^30} | {bottom_middle:^30} | {bottom_right:^30} |")?;
+        writeln!(f, "----------------------------------------------------------------------------------------------------\n")
+    }
+}
+
+// Lee Sallows' square at center 425, the fixture the tests share.
+#[cfg(test)]
+pub const SALLOWS: [u128; 9] = [139129, 83521, 319225, 360721, 180625, 529, 42025, 277729, 222121];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_marks_which_cells_are_squares() {
+        let cells = [139129, 0, 277729, 319225, 180625, 42025, 83521, 529, 0];
+        let candidate = MagicSquareCandidate::new(Pattern::Pattern3, 425, cells);
+
+        assert_eq!(candidate.squares, [true, false, true, true, true, true, true, true, false]);
+    }
+
+    #[test]
+    fn it_classifies_the_pattern_from_the_non_square_cells() {
+        let squares_except = |cells: &[usize]| std::array::from_fn(|i| !cells.contains(&i));
+
+        assert_eq!(Pattern::classify(&squares_except(&[1, 7])), Some(Pattern::Pattern1));
+        assert_eq!(Pattern::classify(&squares_except(&[1, 5])), Some(Pattern::Pattern2));
+        assert_eq!(Pattern::classify(&squares_except(&[1, 8])), Some(Pattern::Pattern4));
+        assert_eq!(Pattern::classify(&squares_except(&[2, 5])), Some(Pattern::Pattern3));
+        assert_eq!(Pattern::classify(&squares_except(&[3, 8])), Some(Pattern::Pattern4));
+        assert_eq!(Pattern::classify(&squares_except(&[6, 8])), Some(Pattern::Pattern5));
+        assert_eq!(Pattern::classify(&squares_except(&[2, 6])), Some(Pattern::Pattern6));
+
+        let sallows = MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS);
+        assert_eq!(Pattern::classify(&sallows.squares), Some(Pattern::Pattern4));
+
+        assert_eq!(Pattern::classify(&squares_except(&[4, 8])), None);
+        assert_eq!(Pattern::classify(&squares_except(&[1])), None);
+        assert_eq!(Pattern::classify(&squares_except(&[0, 1, 2])), None);
+    }
+
+    #[test]
+    fn it_turns_every_orientation_into_the_same_canonical_one() {
+        let sallows = MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS);
+
+        let mut canonical = sallows.clone();
+        canonical.canonicalize();
+        assert_eq!(canonical.cells, [42025, 277729, 222121, 360721, 180625, 529, 139129, 83521, 319225]);
+        assert_eq!(canonical.squares, canonical.cells.map(is_square));
+
+        for symmetry in SYMMETRIES {
+            let mut candidate = MagicSquareCandidate::new(Pattern::Pattern4, 425, symmetry.map(|i| sallows.cells[i]));
+            candidate.canonicalize();
+            assert_eq!(candidate, canonical);
+        }
+    }
+
+    #[test]
+    fn it_divides_the_cells_by_their_greatest_common_divisor() {
+        let sallows = MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS);
+        let scaled = MagicSquareCandidate::new(Pattern::Pattern4, 1275, SYMMETRIES[1].map(|i| sallows.cells[i] * 9));
+
+        assert_eq!(sallows.scale, 1);
+        assert_eq!(scaled.scale, 9);
+        assert_eq!(scaled.primitive_cells(), sallows.primitive_cells());
+    }
+
+    #[test]
+    fn it_can_format_a_candidate_as_a_json_line() {
+        let mut candidate = MagicSquareCandidate::new(Pattern::Pattern4, 425, [139129, 0, 277729, 319225, 180625, 42025, 83521, 529, 360721]);
+        candidate.factors = vec![5, 5, 17];
+
+        assert_eq!(candidate.to_json(), concat!(
+            r#"{"pattern":4,"center":425,"cells":[139129,0,277729,319225,180625,42025,83521,529,360721],"#,
+            r#""squares":[true,false,true,true,true,true,true,true,false],"factors":[5,5,17],"scale":1,"scaled_copy":false,"known":null,"verdict":"unverified"}"#,
+        ));
+
+        candidate.known = Some("Sallows' \"near miss\"".to_string());
+        assert!(candidate.to_json().contains(r#""known":"Sallows' \"near miss\"","#));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{MagicSquareCandidate, Pattern, SearchMode, parse_modes};
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+
+// A snapshot of the enumeration state of a CompositeNumber, taken between
+// batches of final terms so that every center before the snapshot has been
+// fully checked and none after it has been started.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub num_triples: usize,
+    pub num_factors: RangeInclusive<usize>,
+    pub search_range: Range<u64>,
+    pub search_interval: u64,
+    pub non_final_terms: Vec<TermState>,
+    // The primes still to be tried as the final term after the non-final terms.
+    pub final_terms: RangeInclusive<u64>,
+    // The patterns checked, which a resumed search has to check as well.
+    pub search_modes: Vec<SearchMode>,
+    pub min_squares: usize,
+    // The near-miss board of the current range so far.
+    pub near_misses: Vec<MagicSquareCandidate>,
+    // The primitive squares reported so far and the scaled squares of the
+    // current range still waiting to be compared with them.
+    pub primitives: Vec<[u128; 9]>,
+    pub held_back: Vec<MagicSquareCandidate>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TermState {
+    pub current_triple: (u64, u64, u64, u32),
+    pub next_index: usize,
+    pub end_index: usize,
+}
+
+const VERSION: &str = "magic_square_of_squares checkpoint v2";
+
+const PATTERNS: [Pattern; 8] = [Pattern::Pattern1, Pattern::Pattern2, Pattern::Pattern3, Pattern::Pattern4, Pattern::Pattern5, Pattern::Pattern6, Pattern::Unclassified, Pattern::SemiMagic];
+
+// An empty range of final terms, written without a reversed range literal.
+pub const NO_FINAL_TERMS: RangeInclusive<u64> = RangeInclusive::new(1, 0);
+
+impl Checkpoint {
+    // Write to a temporary file first so a crash mid-write never leaves a
+    // truncated checkpoint in place of the previous one.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, self.to_string())?;
+        fs::rename(&temporary_path, path)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|message| Error::new(ErrorKind::InvalidData, format!("{}: {message}", path.display())))
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut lines = contents.lines();
+        if lines.next() != Some(VERSION) { return Err(format!("expected the first line to be {VERSION:?}")); }
+
+        let mut checkpoint = Self {
+            num_triples: 0,
+            num_factors: 0..=0,
+            search_range: 0..0,
+            search_interval: 0,
+            non_final_terms: vec![],
+            final_terms: NO_FINAL_TERMS,
+            search_modes: vec![],
+            min_squares: 0,
+            near_misses: vec![],
+            primitives: vec![],
+            held_back: vec![],
+        };
+
+        for line in lines {
+            let mut words = line.split_whitespace();
+            let key = words.next().unwrap_or_default();
+
+            match key {
+                "search_modes" => { checkpoint.search_modes = parse_modes(words.next().unwrap_or_default())?; continue; },
+                "near_miss" => { checkpoint.near_misses.push(parse_candidate(line, words)?); continue; },
+                "held_back" => { checkpoint.held_back.push(parse_candidate(line, words)?); continue; },
+                "primitive" => { checkpoint.primitives.push(parse_cells(line, words)?); continue; },
+                _ => {},
+            }
+
+            let values = words.map(|w| w.parse::<u64>().map_err(|_| format!("invalid number {w:?} on line {line:?}"))).collect::<Result<Vec<_>, _>>()?;
+
+            match (key, values.as_slice()) {
+                ("num_triples", &[n]) => checkpoint.num_triples = n as usize,
+                ("num_factors", &[min, max]) => checkpoint.num_factors = min as usize..=max as usize,
+                ("search_range", &[start, end]) => checkpoint.search_range = start..end,
+                ("search_interval", &[n]) => checkpoint.search_interval = n,
+                ("final_terms", &[min, max]) => checkpoint.final_terms = min..=max,
+                ("min_squares", &[n]) => checkpoint.min_squares = n as usize,
+                ("term", &[a, b, c, f, next, end]) => checkpoint.non_final_terms.push(TermState { current_triple: (a, b, c, f as u32), next_index: next as usize, end_index: end as usize }),
+                ("", []) => {},
+                _ => return Err(format!("unexpected line {line:?}")),
+            }
+        }
+
+        if checkpoint.search_interval == 0 { return Err("missing search_interval".to_string()); }
+        if checkpoint.non_final_terms.len() + 1 != *checkpoint.num_factors.end() { return Err("expected one term line per non-final term".to_string()); }
+
+        Ok(checkpoint)
+    }
+
+    // Centers before and after the checkpoint would otherwise be searched for
+    // different patterns without anything in the output saying so.
+    pub fn check_search(&self, search_modes: &[SearchMode], min_squares: usize) -> Result<(), String> {
+        let same_modes = self.search_modes.len() == search_modes.len() && search_modes.iter().all(|mode| self.search_modes.contains(mode));
+        if same_modes && self.min_squares == min_squares { return Ok(()); }
+
+        let names = |modes: &[SearchMode]| modes.iter().map(|mode| mode.name()).collect::<Vec<_>>().join(",");
+        Err(format!("The checkpoint was taken with --mode {} --min-squares {} but this search uses --mode {} --min-squares {min_squares}.", names(&self.search_modes), self.min_squares, names(search_modes)))
+    }
+}
+
+// A candidate is written as its pattern, center, whether it is a scaled copy,
+// its cells and its factors. The rest is derived from those when it's restored.
+fn parse_candidate<'a>(line: &str, mut words: impl Iterator<Item = &'a str>) -> Result<MagicSquareCandidate, String> {
+    let pattern = words.next().and_then(|word| PATTERNS.into_iter().find(|pattern| format!("{pattern:?}") == word)).ok_or(format!("invalid pattern on line {line:?}"))?;
+    let values = words.map(|w| w.parse::<u128>().map_err(|_| format!("invalid number {w:?} on line {line:?}"))).collect::<Result<Vec<_>, _>>()?;
+
+    let [center, scaled_copy, cells @ ..] = values.as_slice() else { return Err(format!("missing cells on line {line:?}")) };
+    let Some((cells, factors)) = cells.split_first_chunk::<9>() else { return Err(format!("missing cells on line {line:?}")) };
+    let center = u64::try_from(*center).map_err(|_| format!("invalid center on line {line:?}"))?;
+    let factors = factors.iter().map(|&f| u64::try_from(f).map_err(|_| format!("invalid factor on line {line:?}"))).collect::<Result<Vec<_>, _>>()?;
+
+    Ok(MagicSquareCandidate { factors, scaled_copy: *scaled_copy == 1, ..MagicSquareCandidate::new(pattern, center, *cells) })
+}
+
+fn parse_cells<'a>(line: &str, words: impl Iterator<Item = &'a str>) -> Result<[u128; 9], String> {
+    let values = words.map(|w| w.parse::<u128>().map_err(|_| format!("invalid number {w:?} on line {line:?}"))).collect::<Result<Vec<_>, _>>()?;
+    values.try_into().map_err(|_| format!("expected nine cells on line {line:?}"))
+}
+
+fn write_candidate(f: &mut std::fmt::Formatter, key: &str, candidate: &MagicSquareCandidate) -> std::fmt::Result {
+    write!(f, "{key} {:?} {} {}", candidate.pattern, candidate.center, candidate.scaled_copy as u8)?;
+    for value in candidate.cells.iter().map(u128::to_string).chain(candidate.factors.iter().map(u64::to_string)) { write!(f, " {value}")?; }
+    writeln!(f)
+}
+
+impl std::fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{VERSION}")?;
+        writeln!(f, "num_triples {}", self.num_triples)?;
+        writeln!(f, "num_factors {} {}", self.num_factors.start(), self.num_factors.end())?;
+        writeln!(f, "search_range {} {}", self.search_range.start, self.search_range.end)?;
+        writeln!(f, "search_interval {}", self.search_interval)?;
+        writeln!(f, "final_terms {} {}", self.final_terms.start(), self.final_terms.end())?;
+        writeln!(f, "min_squares {}", self.min_squares)?;
+
+        if !self.search_modes.is_empty() {
+            writeln!(f, "search_modes {}", self.search_modes.iter().map(|mode| mode.name()).collect::<Vec<_>>().join(","))?;
+        }
+
+        for term in &self.non_final_terms {
+            let (a, b, c, factors) = term.current_triple;
+            writeln!(f, "term {a} {b} {c} {factors} {} {}", term.next_index, term.end_index)?;
+        }
+
+        for candidate in &self.near_misses {
+            write_candidate(f, "near_miss", candidate)?;
+        }
+
+        for candidate in &self.held_back {
+            write_candidate(f, "held_back", candidate)?;
+        }
+
+        for cells in &self.primitives {
+            writeln!(f, "primitive {}", cells.map(|cell| cell.to_string()).join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SALLOWS;
+
+    #[test]
+    fn it_can_round_trip_a_checkpoint_through_its_text_format() {
+        let checkpoint = Checkpoint {
+            num_triples: 100,
+            num_factors: 2..=3,
+            search_range: 1000..2000,
+            search_interval: 1000,
+            non_final_terms: vec![
+                TermState { current_triple: (0, 0, 1, 0), next_index: 0, end_index: 100 },
+                TermState { current_triple: (5, 12, 13, 1), next_index: 2, end_index: 7 },
+            ],
+            final_terms: 17..=89,
+            search_modes: vec![SearchMode::Patterns234, SearchMode::Graph],
+            min_squares: 8,
+            near_misses: vec![
+                MagicSquareCandidate { factors: vec![5, 5, 17], ..MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS) },
+                MagicSquareCandidate { scaled_copy: true, ..MagicSquareCandidate::new(Pattern::Unclassified, 1275, SALLOWS.map(|cell| cell * 9)) },
+            ],
+            primitives: vec![SALLOWS],
+            held_back: vec![MagicSquareCandidate::new(Pattern::SemiMagic, 2125, SALLOWS.map(|cell| cell * 25))],
+        };
+
+        assert_eq!(Checkpoint::parse(&checkpoint.to_string()), Ok(checkpoint));
+    }
+
+    #[test]
+    fn it_rejects_a_search_with_different_patterns_from_the_checkpoint() {
+        let checkpoint = Checkpoint::parse(&format!("{VERSION}\nsearch_interval 10\nnum_factors 2 2\nterm 0 0 1 0 0 10\nsearch_modes patterns234,graph\nmin_squares 8\n")).unwrap();
+
+        assert!(checkpoint.check_search(&[SearchMode::Graph, SearchMode::Patterns234], 8).is_ok());
+        assert!(checkpoint.check_search(&[SearchMode::Graph], 8).is_err());
+        assert!(checkpoint.check_search(&[SearchMode::Graph, SearchMode::Patterns16], 8).is_err());
+        assert!(checkpoint.check_search(&[SearchMode::Graph, SearchMode::Patterns234], 7).is_err());
+    }
+
+    #[test]
+    fn it_rejects_malformed_checkpoints() {
+        assert!(Checkpoint::parse("").is_err());
+        assert!(Checkpoint::parse(&format!("{VERSION}\nnum_factors 2 3\n")).is_err());
+        assert!(Checkpoint::parse(&format!("{VERSION}\nsearch_interval ten\n")).is_err());
+        assert!(Checkpoint::parse(&format!("{VERSION}\nsearch_interval 10\nnum_factors 2 3\n")).is_err());
+        assert!(Checkpoint::parse(&format!("{VERSION}\nsearch_interval 10\nsearch_modes graphs\n")).is_err());
+        assert!(Checkpoint::parse(&format!("{VERSION}\nsearch_interval 10\nnear_miss Pattern7 425 0 1 2 3 4 5 6 7 8 9\n")).is_err());
+        assert!(Checkpoint::parse(&format!("{VERSION}\nsearch_interval 10\nnear_miss Pattern4 425 0 1 2 3\n")).is_err());
+        assert!(Checkpoint::parse(&format!("{VERSION}\nsearch_interval 10\nprimitive 1 2 3 4 5 6 7 8\n")).is_err());
+    }
+}
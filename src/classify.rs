@@ -0,0 +1,191 @@
+use crate::{CompositeNumber, MagicSquareCandidate, Pattern, PatternRegistry, SearchMode, Verdict, canonical_orientation, gcd, is_square, verify, MAX_CENTER};
+use std::sync::Mutex;
+
+// Explains a grid found in the literature or in our logs: which figure 5
+// pattern it is, which of its cells are squares and whether the checker for
+// that pattern would find it at its center.
+
+pub const CELL_NAMES: [&str; 9] = [
+    "top left", "top middle", "top right",
+    "middle left", "center", "middle right",
+    "bottom left", "bottom middle", "bottom right",
+];
+
+pub struct Classification {
+    // The cells were divided by the square of this to normalize them.
+    pub divisor: u128,
+    pub candidate: MagicSquareCandidate,
+}
+
+// Divide the cells by the largest square that divides all of them and turn the
+// grid into its canonical orientation. The center of the result has to be a
+// square for the grid to be a candidate from the search.
+pub fn classify(cells: [u128; 9]) -> Result<Classification, String> {
+    if cells.contains(&0) { return Err("The cells must all be greater than zero.".to_string()); }
+
+    let divisor = largest_square_divisor(&cells);
+    let cells = canonical_orientation(cells.map(|cell| cell / (divisor * divisor)));
+
+    if !is_square(cells[4]) { return Err(format!("The center cell {} isn't a square.", cells[4])); }
+
+    let center = cells[4].isqrt();
+    if center > MAX_CENTER as u128 { return Err(format!("The center {center} is larger than the largest supported center {MAX_CENTER}.")); }
+
+    let mut candidate = MagicSquareCandidate::new(Pattern::Unclassified, center as u64, cells);
+    candidate.pattern = Pattern::classify(&candidate.squares).unwrap_or(Pattern::Unclassified);
+    candidate.verdict = verify(&candidate);
+
+    Ok(Classification { divisor, candidate })
+}
+
+// A magic grid with more than seven squares fits several patterns and isn't
+// classified, but the graph checker finds any grid with at least seven.
+// The largest d whose square divides every cell. It divides the roots of the
+// square cells so only the primes of their gcd r are tried, up to the cube root
+// of what is left of r. At most two primes remain after that, either as a
+// square or as a squarefree m, whose part with a square dividing the cells is
+// t / gcd(t, m) for t = gcd(m², cells).
+fn largest_square_divisor(cells: &[u128; 9]) -> u128 {
+    let root_gcd = cells.iter().filter(|&&cell| is_square(cell)).fold(0, |divisor, &cell| gcd(divisor, cell.isqrt()));
+    if root_gcd <= 1 { return 1; }
+
+    let cell_gcd = cells.iter().fold(root_gcd * root_gcd, |divisor, &cell| gcd(divisor, cell));
+    let square_root_of_power = |p: u128| {
+        let (mut power, mut rest) = (1, cell_gcd);
+        while rest.is_multiple_of(p * p) { rest /= p * p; power *= p; }
+        power
+    };
+
+    let (mut divisor, mut rest) = (1, root_gcd);
+    let mut p = 2;
+
+    while p * p * p <= rest {
+        if rest.is_multiple_of(p) {
+            while rest.is_multiple_of(p) { rest /= p; }
+            divisor *= square_root_of_power(p);
+        }
+        p += if p == 2 { 1 } else { 2 };
+    }
+
+    if rest > 1 && is_square(rest) {
+        divisor * square_root_of_power(rest.isqrt())
+    } else {
+        let t = gcd(rest * rest, cell_gcd);
+        divisor * (t / gcd(t, rest))
+    }
+}
+
+pub fn checker_for(candidate: &MagicSquareCandidate) -> Option<SearchMode> {
+    match candidate.pattern {
+        Pattern::Pattern1 | Pattern::Pattern6 => Some(SearchMode::Patterns16),
+        Pattern::Pattern2 | Pattern::Pattern3 | Pattern::Pattern4 => Some(SearchMode::Patterns234),
+        Pattern::Pattern5 => Some(SearchMode::Patterns5),
+        Pattern::Unclassified if candidate.squares.iter().filter(|&&s| s).count() > 7 => Some(SearchMode::Graph),
+        Pattern::Unclassified | Pattern::SemiMagic => None,
+    }
+}
+
+// Run the checker on the magic triples of the candidate's center and look for
+// the candidate among the squares it reports.
+pub fn is_found_by(candidate: &MagicSquareCandidate, search_mode: SearchMode) -> Result<bool, String> {
+    let registry = PatternRegistry::for_modes(&[search_mode], 7);
+    let mut composite_number = CompositeNumber::for_center(candidate.center, registry.triple_orders())?;
+    let found = Mutex::new(false);
+
+    composite_number.for_each_in_search_range(|triples| registry.check_each(triples, &|other| {
+        if canonical_orientation(other.cells) == candidate.cells { *found.lock().unwrap() = true; }
+    }));
+
+    Ok(found.into_inner().unwrap())
+}
+
+impl Classification {
+    pub fn is_magic(&self) -> bool {
+        matches!(self.candidate.verdict, Verdict::VerifiedMagic | Verdict::NearMiss)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SALLOWS;
+
+    #[test]
+    fn it_normalizes_a_scaled_and_rotated_grid() {
+        let rotated = [6, 3, 0, 7, 4, 1, 8, 5, 2].map(|i| SALLOWS[i] * 9);
+        let classification = classify(rotated).unwrap();
+
+        assert_eq!(classification.divisor, 3);
+        assert_eq!(classification.candidate.center, 425);
+        assert_eq!(classification.candidate.cells, canonical_orientation(SALLOWS));
+        assert_eq!(classification.candidate.pattern, Pattern::Pattern4);
+        assert!(classification.is_magic());
+    }
+
+    #[test]
+    fn it_divides_by_the_largest_square_that_divides_every_cell() {
+        // The roots of the square cells share a factor of 6 but only 4 divides
+        // the other cells.
+        let cells = SALLOWS.map(|cell| if is_square(cell) { cell * 36 } else { cell * 4 });
+        let classification = classify(cells).unwrap();
+
+        assert_eq!(classification.divisor, 2);
+        assert_eq!(classification.candidate.center, 425 * 3);
+
+        // Large primes that are left over after trial division, on their own,
+        // squared and as a product of two.
+        let (p, q): (u128, u128) = (1_000_003, 4_294_967_311);
+        let cases = [(1, 1, 1), (4, 2, 1), (9, 9, 3), (25, 125, 5), (p * p, p, 1), (9 * p * p, 3 * p * p, p), (p * p * q * q, p * p * q, p), (p * p * p * p, p * p * p, p)];
+
+        for (square, other, expected) in cases {
+            let cells = [square, square * 4, square * 9, other * 2, square * 16, other * 3, square * 25, other * 7, square * 36];
+            assert_eq!(largest_square_divisor(&cells), expected, "{cells:?}");
+        }
+
+        assert_eq!(largest_square_divisor(&[2, 3, 5, 6, 7, 8, 10, 11, 12]), 1);
+    }
+
+    #[test]
+    fn it_confirms_the_checker_for_the_pattern_finds_the_grid() {
+        let candidate = classify(SALLOWS).unwrap().candidate;
+
+        assert_eq!(checker_for(&candidate), Some(SearchMode::Patterns234));
+        assert_eq!(is_found_by(&candidate, SearchMode::Patterns234), Ok(true));
+        assert_eq!(is_found_by(&candidate, SearchMode::Patterns16), Ok(false));
+    }
+
+    #[test]
+    fn it_falls_back_to_the_graph_checker_for_grids_with_more_than_seven_squares() {
+        // Sallows' square with one of its non-square cells made square. It isn't
+        // magic, as no eight-square grid is known, but it is unclassified like one.
+        let mut cells = SALLOWS;
+        let non_square = (0..9).find(|&i| !is_square(cells[i])).unwrap();
+        cells[non_square] = 4;
+
+        let mut candidate = MagicSquareCandidate::new(Pattern::Unclassified, 425, canonical_orientation(cells));
+        assert_eq!(candidate.squares.iter().filter(|&&s| s).count(), 8);
+        assert_eq!(Pattern::classify(&candidate.squares), None);
+        assert_eq!(checker_for(&candidate), Some(SearchMode::Graph));
+
+        candidate.verdict = verify(&candidate);
+        assert_ne!(candidate.verdict, Verdict::VerifiedMagic);
+        assert_eq!(is_found_by(&candidate, SearchMode::Graph), Ok(false));
+
+        // The graph checker does find the seven squares of Sallows' square.
+        let sallows = classify(SALLOWS).unwrap().candidate;
+        assert_eq!(is_found_by(&sallows, SearchMode::Graph), Ok(true));
+
+        let six_squares = MagicSquareCandidate::new(Pattern::Unclassified, 425, [2, 3, 5, 6, 180625, 7, 8, 10, 11]);
+        assert_eq!(checker_for(&six_squares), None);
+    }
+
+    #[test]
+    fn it_explains_grids_that_are_not_candidates() {
+        assert!(classify([1, 2, 3, 4, 5, 6, 7, 8, 9]).is_err());
+        assert!(classify([1, 2, 3, 4, 0, 6, 7, 8, 9]).is_err());
+
+        let not_magic = classify([1, 4, 9, 16, 25, 36, 49, 64, 81]).unwrap();
+        assert_eq!(not_magic.candidate.pattern, Pattern::Unclassified);
+        assert!(!not_magic.is_magic());
+    }
+}
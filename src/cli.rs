@@ -0,0 +1,426 @@
+use crate::{OutputFormat, MAX_CENTER, MAX_SEMI_MAGIC_CENTER};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: magic_square_of_squares <command> [options]
+
+Commands:
+  search                 Search composite centers for magic squares of squares (runs until stopped
+                         or the largest supported center, about 1.065 x 10^19, is reached)
+  triples                Compute the primitive Pythagorean triples table, print a summary and
+                         write it to --triples-cache if given
+  check <center>         Run the pattern checkers on the magic triples of a single center
+  classify <9 cells>     Normalize a grid given row by row, name its figure 5 pattern and check
+                         that the checker for that pattern finds it at its center
+  help                   Print this message
+
+Options:
+  --num-triples <n>      Number of primitive Pythagorean triples to compute [default: 500_000_000]
+                         (must reach the square root of the search end; the final terms past
+                         the table are computed per range)
+  --mode <modes>         Comma-separated patterns to check in one pass: patterns16, patterns234,
+                         patterns5 (excluded from the search by default), graph (every
+                         pattern, slower) or semimagic (a self test that finds semi-magic
+                         squares of squares for centers below 2^21) [default: patterns16]
+  --min-squares <k>      Number of square cells the graph mode looks for and a near miss needs to
+                         be ranked, from 7 to 9 [default: 7]
+  --near-misses <n>      Rank the near misses and only write the best n of each search range when
+                         it finishes, each with its rank and score
+  --start <n>            Start of the first search range [default: 0]
+  --interval <n>         Size of each search range [default: 100_000_000_000]
+  --print-factors        Print the non-final factors of each batch of composite numbers
+  --known-solutions <file>
+                         Tag candidates that match this catalogue of known squares instead of
+                         the built-in one (see known_solutions.txt for the format)
+  --show-scaled-copies   Write squares whose cells divided by their gcd were already reported at
+                         another center, tagged as scaled copies, instead of hiding them
+  --output <file>        Append found candidates to this file instead of stdout
+  --format <format>      Write candidates as json (one JSON object per line) or box [default: json]
+  --triples-cache <file> Load the triples table from a file written by the triples command
+                         (replaces --num-triples for search)
+  --checkpoint <file>    Periodically save the search state to this file
+  --checkpoint-interval <seconds>
+                         How often to save the search state [default: 600]
+  --resume <file>        Continue a search from a checkpoint (replaces --start, --interval and
+                         --num-triples, and --mode and --min-squares must match the checkpoint)
+                         and keep checkpointing to it unless --checkpoint is given";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchMode { Patterns16, Patterns234, Patterns5, Graph, SemiMagic }
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Search(SearchOptions),
+    Triples(TriplesOptions),
+    Check(CheckOptions),
+    Classify(ClassifyOptions),
+    Help,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub num_triples: usize,
+    pub search_modes: Vec<SearchMode>,
+    pub min_squares: usize,
+    pub near_misses: Option<usize>,
+    pub start: u64,
+    pub interval: u64,
+    pub print_factors: bool,
+    pub known_solutions: Option<PathBuf>,
+    pub show_scaled_copies: bool,
+    pub triples_cache: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    pub resume: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TriplesOptions {
+    pub num_triples: usize,
+    pub triples_cache: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheckOptions {
+    pub center: u64,
+    pub search_modes: Vec<SearchMode>,
+    pub min_squares: usize,
+    pub near_misses: Option<usize>,
+    pub known_solutions: Option<PathBuf>,
+    pub show_scaled_copies: bool,
+    pub output: Option<PathBuf>,
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ClassifyOptions {
+    pub cells: [u128; 9],
+}
+
+impl SearchMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SearchMode::Patterns16 => "patterns16",
+            SearchMode::Patterns234 => "patterns234",
+            SearchMode::Patterns5 => "patterns5",
+            SearchMode::Graph => "graph",
+            SearchMode::SemiMagic => "semimagic",
+        }
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            num_triples: 500_000_000,
+            search_modes: vec![SearchMode::Patterns16],
+            min_squares: 7,
+            near_misses: None,
+            start: 0,
+            interval: 100_000_000_000,
+            print_factors: false,
+            known_solutions: None,
+            show_scaled_copies: false,
+            triples_cache: None,
+            checkpoint: None,
+            checkpoint_interval: Duration::from_secs(600),
+            resume: None,
+            output: None,
+            format: OutputFormat::JsonLines,
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn start_range(&self) -> Range<u64> {
+        self.start..self.start.saturating_add(self.interval)
+    }
+}
+
+impl Command {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let Some(command) = args.next() else { return Err("No command given.".to_string()) };
+
+        let mut options = SearchOptions::default();
+        let mut positional = vec![];
+        let mut given = vec![];
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("Missing value for {name}."));
+
+            match arg.as_str() {
+                "--num-triples" => options.num_triples = parse_number(&value(&arg)?, &arg)?,
+                "--mode" => options.search_modes = parse_modes(&value(&arg)?)?,
+                "--min-squares" => options.min_squares = parse_number(&value(&arg)?, &arg)?,
+                "--near-misses" => options.near_misses = Some(parse_number(&value(&arg)?, &arg)?),
+                "--start" => options.start = parse_number(&value(&arg)?, &arg)?,
+                "--interval" => options.interval = parse_number(&value(&arg)?, &arg)?,
+                "--print-factors" => options.print_factors = true,
+                "--known-solutions" => options.known_solutions = Some(value(&arg)?.into()),
+                "--show-scaled-copies" => options.show_scaled_copies = true,
+                "--triples-cache" => options.triples_cache = Some(value(&arg)?.into()),
+                "--checkpoint" => options.checkpoint = Some(value(&arg)?.into()),
+                "--checkpoint-interval" => options.checkpoint_interval = Duration::from_secs(parse_number(&value(&arg)?, &arg)?),
+                "--resume" => options.resume = Some(value(&arg)?.into()),
+                "--output" => options.output = Some(value(&arg)?.into()),
+                "--format" => options.format = parse_format(&value(&arg)?)?,
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}.")),
+                _ => { positional.push(arg); continue },
+            }
+
+            given.push(arg);
+        }
+
+        if options.num_triples == 0 { return Err("--num-triples must be greater than zero.".to_string()); }
+        if !(7..=9).contains(&options.min_squares) { return Err("--min-squares must be from 7 to 9.".to_string()); }
+        if options.near_misses == Some(0) { return Err("--near-misses must be greater than zero.".to_string()); }
+        if options.interval == 0 { return Err("--interval must be greater than zero.".to_string()); }
+        if options.start > MAX_CENTER { return Err(format!("--start must be at most {MAX_CENTER}, the largest supported center.")); }
+        if options.checkpoint_interval.is_zero() { return Err("--checkpoint-interval must be greater than zero.".to_string()); }
+        if options.search_modes.contains(&SearchMode::SemiMagic) && options.start > MAX_SEMI_MAGIC_CENTER { return Err(format!("--mode semimagic only finds squares for centers up to {MAX_SEMI_MAGIC_CENTER} so --start must be at most that.")); }
+
+        let command = match command.as_str() {
+            "search" => Command::Search(options),
+            "triples" => Command::Triples(TriplesOptions { num_triples: options.num_triples, triples_cache: options.triples_cache }),
+            "check" => {
+                let [center] = positional.as_slice() else { return Err("The check command takes exactly one center.".to_string()) };
+                let center = parse_number(center, "center")?;
+                if center < 25 { return Err("The center must be at least 25 (the smallest with two Pythagorean prime factors).".to_string()); }
+                if center > MAX_CENTER { return Err(format!("The center must be at most {MAX_CENTER}, the largest supported center.")); }
+                if options.search_modes.contains(&SearchMode::SemiMagic) && center > MAX_SEMI_MAGIC_CENTER { return Err(format!("--mode semimagic only finds squares for centers up to {MAX_SEMI_MAGIC_CENTER}.")); }
+
+                Command::Check(CheckOptions {
+                    center,
+                    search_modes: options.search_modes,
+                    min_squares: options.min_squares,
+                    near_misses: options.near_misses,
+                    known_solutions: options.known_solutions,
+                    show_scaled_copies: options.show_scaled_copies,
+                    output: options.output,
+                    format: options.format,
+                })
+            },
+            "classify" => {
+                let Ok(cells) = <[String; 9]>::try_from(positional.clone()) else { return Err("The classify command takes exactly nine cells.".to_string()) };
+                let cells = cells.iter().map(|cell| parse_number(cell, "cell")).collect::<Result<Vec<_>, _>>()?;
+
+                Command::Classify(ClassifyOptions { cells: cells.try_into().unwrap() })
+            },
+            "help" | "--help" | "-h" => Command::Help,
+            _ => return Err(format!("Unknown command {command}.")),
+        };
+
+        if let (Some(arg), false) = (positional.first(), matches!(command, Command::Check(_) | Command::Classify(_))) {
+            return Err(format!("Unexpected argument {arg}."));
+        }
+
+        if let Some(arg) = given.iter().find(|arg| !command.accepts(arg)) {
+            return Err(format!("The {} command does not take {arg}.", command.name()));
+        }
+
+        Ok(command)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Search(_) => "search",
+            Command::Triples(_) => "triples",
+            Command::Check(_) => "check",
+            Command::Classify(_) => "classify",
+            Command::Help => "help",
+        }
+    }
+
+    fn accepts(&self, option: &str) -> bool {
+        match self {
+            Command::Search(_) => true,
+            Command::Triples(_) => matches!(option, "--num-triples" | "--triples-cache"),
+            Command::Check(_) => matches!(option, "--mode" | "--min-squares" | "--near-misses" | "--known-solutions" | "--show-scaled-copies" | "--output" | "--format"),
+            Command::Classify(_) | Command::Help => false,
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, String> {
+    value.replace('_', "").parse().map_err(|_| format!("Invalid number {value:?} for {name}."))
+}
+
+pub fn parse_modes(value: &str) -> Result<Vec<SearchMode>, String> {
+    let mut modes = vec![];
+
+    for mode in value.split(',') {
+        let mode = match mode {
+            "patterns16" => SearchMode::Patterns16,
+            "patterns234" => SearchMode::Patterns234,
+            "patterns5" => SearchMode::Patterns5,
+            "graph" => SearchMode::Graph,
+            "semimagic" => SearchMode::SemiMagic,
+            _ => return Err(format!("Unknown mode {mode:?}, expected patterns16, patterns234, patterns5, graph or semimagic.")),
+        };
+
+        if !modes.contains(&mode) { modes.push(mode); }
+    }
+
+    Ok(modes)
+}
+
+fn parse_format(value: &str) -> Result<OutputFormat, String> {
+    match value {
+        "json" => Ok(OutputFormat::JsonLines),
+        "box" => Ok(OutputFormat::Box),
+        _ => Err(format!("Unknown format {value:?}, expected json or box.")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Command, String> {
+        Command::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn it_uses_the_previous_constants_as_defaults_for_search() {
+        assert_eq!(parse("search"), Ok(Command::Search(SearchOptions::default())));
+
+        let Ok(Command::Search(options)) = parse("search") else { unreachable!() };
+        assert_eq!(options.start_range(), 0..100_000_000_000);
+
+        let Ok(Command::Search(options)) = parse("search --start 10650232656628343401") else { unreachable!() };
+        assert_eq!(options.start_range(), MAX_CENTER..MAX_CENTER + 100_000_000_000);
+    }
+
+    #[test]
+    fn it_can_parse_search_options() {
+        let command = parse("search --num-triples 1_000 --mode patterns234 --start 500 --interval 100 --print-factors --known-solutions known.txt --show-scaled-copies");
+
+        assert_eq!(command, Ok(Command::Search(SearchOptions {
+            num_triples: 1000,
+            search_modes: vec![SearchMode::Patterns234],
+            start: 500,
+            interval: 100,
+            print_factors: true,
+            known_solutions: Some("known.txt".into()),
+            show_scaled_copies: true,
+            ..SearchOptions::default()
+        })));
+    }
+
+    #[test]
+    fn it_can_parse_several_modes_to_check_in_one_pass() {
+        let Ok(Command::Search(options)) = parse("search --mode patterns234,patterns16,patterns234") else { unreachable!() };
+        assert_eq!(options.search_modes, &[SearchMode::Patterns234, SearchMode::Patterns16]);
+
+        let Ok(Command::Search(options)) = parse("search --mode patterns5") else { unreachable!() };
+        assert_eq!(options.search_modes, &[SearchMode::Patterns5]);
+
+        let Ok(Command::Search(options)) = parse("search --mode graph --min-squares 8") else { unreachable!() };
+        assert_eq!(options.search_modes, &[SearchMode::Graph]);
+        assert_eq!(options.min_squares, 8);
+
+        let Ok(Command::Search(options)) = parse("search --mode semimagic") else { unreachable!() };
+        assert_eq!(options.search_modes, &[SearchMode::SemiMagic]);
+        assert!(parse("search --mode semimagic --start 2_097_151").is_ok());
+    }
+
+    #[test]
+    fn it_can_parse_checkpoint_options() {
+        let Ok(Command::Search(options)) = parse("search --checkpoint search.checkpoint --checkpoint-interval 60") else { unreachable!() };
+        assert_eq!(options.checkpoint, Some(PathBuf::from("search.checkpoint")));
+        assert_eq!(options.checkpoint_interval, Duration::from_secs(60));
+        assert_eq!(options.resume, None);
+
+        let Ok(Command::Search(options)) = parse("search --resume search.checkpoint") else { unreachable!() };
+        assert_eq!(options.resume, Some(PathBuf::from("search.checkpoint")));
+    }
+
+    #[test]
+    fn it_can_parse_output_options() {
+        let Ok(Command::Search(options)) = parse("search --output found.jsonl --format json") else { unreachable!() };
+        assert_eq!(options.output, Some(PathBuf::from("found.jsonl")));
+        assert_eq!(options.format, OutputFormat::JsonLines);
+        assert_eq!(options.near_misses, None);
+
+        let Ok(Command::Search(options)) = parse("search --near-misses 20 --min-squares 8") else { unreachable!() };
+        assert_eq!(options.near_misses, Some(20));
+        assert_eq!(options.min_squares, 8);
+    }
+
+    #[test]
+    fn it_can_parse_the_triples_and_check_commands() {
+        assert_eq!(parse("triples --num-triples 100"), Ok(Command::Triples(TriplesOptions { num_triples: 100, triples_cache: None })));
+        assert_eq!(parse("triples --triples-cache triples.bin"), Ok(Command::Triples(TriplesOptions { num_triples: 500_000_000, triples_cache: Some("triples.bin".into()) })));
+
+        let Ok(Command::Search(options)) = parse("search --triples-cache triples.bin") else { unreachable!() };
+        assert_eq!(options.triples_cache, Some(PathBuf::from("triples.bin")));
+
+        assert_eq!(parse("check 425 --mode patterns234 --format box"), Ok(Command::Check(CheckOptions {
+            center: 425,
+            search_modes: vec![SearchMode::Patterns234],
+            min_squares: 7,
+            near_misses: None,
+            known_solutions: None,
+            show_scaled_copies: false,
+            output: None,
+            format: OutputFormat::Box,
+        })));
+    }
+
+    #[test]
+    fn it_can_parse_the_classify_command() {
+        let command = parse("classify 139129 83521 319225 360721 180625 529 42025 277729 222_121");
+        assert_eq!(command, Ok(Command::Classify(ClassifyOptions { cells: crate::SALLOWS })));
+
+        assert!(parse("classify 1 2 3 4 5 6 7 8").is_err());
+        assert!(parse("classify 1 2 3 4 5 6 7 8 9 10").is_err());
+        assert!(parse("classify 1 2 3 4 five 6 7 8 9").is_err());
+    }
+
+    #[test]
+    fn it_rejects_invalid_arguments() {
+        assert!(parse("").is_err());
+        assert!(parse("frobnicate").is_err());
+        assert!(parse("search --mode patterns7").is_err());
+        assert!(parse("search --mode patterns16,").is_err());
+        assert!(parse("search --interval 0").is_err());
+        assert!(parse("search --min-squares 6").is_err());
+        assert!(parse("search --min-squares 10").is_err());
+        assert!(parse("search --near-misses 0").is_err());
+        assert!(parse("search --num-triples lots").is_err());
+        assert!(parse("search --start 18446744073709551615").is_err());
+        assert!(parse("search --start 10650232656628343402").is_err());
+        assert!(parse("search --unknown").is_err());
+        assert!(parse("search --start").is_err());
+        assert!(parse("search --checkpoint-interval 0").is_err());
+        assert!(parse("search --resume").is_err());
+        assert!(parse("search --triples-cache").is_err());
+        assert!(parse("search --known-solutions").is_err());
+        assert!(parse("search --format xml").is_err());
+        assert!(parse("search 123").is_err());
+        assert!(parse("check").is_err());
+        assert!(parse("check 24").is_err());
+        assert!(parse("check 25 65").is_err());
+        assert!(parse("check 10650232656628343402").is_err());
+        assert!(parse("search --mode semimagic --start 3000000").is_err());
+        assert!(parse("search --mode patterns16,semimagic --start 2097152").is_err());
+        assert!(parse("check 2097175 --mode semimagic").is_err());
+    }
+
+    #[test]
+    fn it_rejects_options_that_do_not_apply_to_the_command() {
+        assert_eq!(parse("triples --mode graph"), Err("The triples command does not take --mode.".to_string()));
+        assert_eq!(parse("check 425 --checkpoint x"), Err("The check command does not take --checkpoint.".to_string()));
+        assert!(parse("check 425 --start 500").is_err());
+        assert!(parse("check 425 --triples-cache triples.bin").is_err());
+        assert!(parse("triples --output found.jsonl").is_err());
+        assert!(parse("classify 1 2 3 4 5 6 7 8 9 --format box").is_err());
+        assert!(parse("help --print-factors").is_err());
+        assert!(parse("check 425 --mode graph --min-squares 8 --near-misses 5 --known-solutions known.txt --show-scaled-copies --output found.jsonl --format box").is_ok());
+    }
+}
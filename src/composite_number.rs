@@ -1,79 +1,334 @@
-use rayon::prelude::*;
-use crate::{PythagoreanTriples, TemporaryBuffer, SearchMode};
+use crate::{PythagoreanTriples, TriplesTable, TemporaryBuffer, TripleOrder, MagicTriples, Checkpoint, TermState, Scheduler, NO_FINAL_TERMS};
+use rayon::ScopeFifo;
 use std::ops::{Range, RangeInclusive};
 use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// The largest center we can search, about 2^63.2. The magic sum 3c² is the
+// largest value the checkers compute and it has to fit in a u128. The magic
+// triples of c are at most c√2 so they still fit in a u64. All products on the
+// enumeration path are bounded by the end of the search range so none of them
+// can overflow below this ceiling.
+pub const MAX_CENTER: u64 = (u128::MAX / 3).isqrt() as u64;
+
+// The number of final terms queued before they are sorted by cost and handed
+// to the thread pool, and how far the enumeration can get ahead of the pool.
+const SCHEDULER_WINDOW: usize = 10_000;
+
+// The width of each window of final terms that is computed or copied from the
+// table at once. Above the table a window holds about 2^23 / ln(p) primes.
+const FINAL_TERM_WINDOW: u64 = 1 << 24;
+
+type SaveSearchState = Box<dyn Fn(&mut Checkpoint)>;
 
 pub struct CompositeNumber {
     num_factors: RangeInclusive<usize>,
     non_final_terms: Box<[NonFinalTerm]>,
-    final_term_start_index: usize,
-    final_term_end_index: usize,
+    final_terms: RangeInclusive<u64>,
     search_range: Range<u64>,
-    pythagorean_triples: PythagoreanTriples,
+    search_interval: u64,
+    triple_orders: Vec<TripleOrder>,
+    print_factors: bool,
+    pythagorean_triples: TriplesTable,
     temporary_buffer: TemporaryBuffer,
-    triples_buffer: Vec<(u64, u64, u64)>,
+    checkpoint_path: Option<PathBuf>,
+    checkpoint_interval: Duration,
+    last_checkpoint: Instant,
+    save_search_state: Option<SaveSearchState>,
+    // A checkpoint that failed in the middle of a range, returned by for_each.
+    checkpoint_error: Option<String>,
+    min_triples: usize,
 }
 
+// The exponent of the current prime in the cumulative product and the number
+// of divisors of its square are tracked so that the number of magic triples of
+// each center is known before any of them are built.
 struct NonFinalTerm {
     current_triple: (u64, u64, u64, u32),
     cumulative_product: u64,
+    exponent: u32,
+    square_divisors: u64,
     triples_powerset: PythagoreanTriples,
     next_index: usize,
     end_index: usize,
 }
 
+// What every final term after the same non-final terms has in common. It's
+// shared by their tasks so the non-final terms can move on while they run.
+struct NonFinalTerms {
+    product: u64,
+    last_triple: (u64, u64, u64, u32),
+    triples_powerset: PythagoreanTriples,
+    factors: Vec<u64>,
+}
+
+struct FinalTerm {
+    non_final_terms: Arc<NonFinalTerms>,
+    triple: (u64, u64, u64),
+}
+
 impl CompositeNumber {
-    pub fn new(num_factors: RangeInclusive<usize>, start_range: Range<u64>, pythagorean_triples: PythagoreanTriples) -> Self {
+    pub fn new(num_factors: RangeInclusive<usize>, start_range: Range<u64>, triple_orders: Vec<TripleOrder>, print_factors: bool, pythagorean_triples: impl Into<TriplesTable>) -> Self {
+        let pythagorean_triples = pythagorean_triples.into();
         let min_factors = *num_factors.start();
         let max_factors = *num_factors.end();
         assert!(min_factors >= 2);
+        assert!(start_range.start <= MAX_CENTER, "The search range starts above the largest supported center.");
+
+        let search_interval = start_range.end - start_range.start;
+        let start_range = start_range.start..start_range.end.min(MAX_CENTER + 1);
 
         let mut composite_number = Self {
             num_factors,
             non_final_terms: (0..max_factors - 1).map(|_| NonFinalTerm::new(pythagorean_triples.len())).collect(),
-            final_term_start_index: 0,
-            final_term_end_index: 0,
+            final_terms: NO_FINAL_TERMS,
+            search_interval,
             search_range: start_range,
+            triple_orders,
+            print_factors,
             pythagorean_triples,
             temporary_buffer: TemporaryBuffer::default(),
-            triples_buffer: Vec::with_capacity(10_000),
+            checkpoint_path: None,
+            checkpoint_interval: Duration::MAX,
+            last_checkpoint: Instant::now(),
+            save_search_state: None,
+            checkpoint_error: None,
+            min_triples: 0,
         };
 
         composite_number.next_non_final_term(max_factors - min_factors);
         composite_number
     }
 
-    pub fn for_each(&mut self, callback: impl Fn(usize, &mut Vec<u64>, &mut Vec<u64>, u64) + Send + Sync) {
-        loop {
-            println!("Searching composite numbers with {:?} prime factors in the range {:?}.", self.num_factors, self.search_range);
+    // Enumerate just the one center, using the triples of its own prime factors
+    // rather than a table of the first n of them.
+    pub fn for_center(center: u64, triple_orders: Vec<TripleOrder>) -> Result<Self, String> {
+        let factors = Self::prime_factors(center);
+
+        if let Some(prime) = factors.iter().find(|&&p| p % 4 != 1) {
+            return Err(format!("The center {center} has the factor {prime} which is not a Pythagorean prime."));
+        }
+
+        if factors.len() < 2 {
+            return Err(format!("The center {center} is prime so it only has one magic triple."));
+        }
+
+        let mut primes = factors.clone();
+        primes.dedup();
+
+        let pythagorean_triples = PythagoreanTriples::for_primes(primes.into_iter(), factors.len());
+        Ok(Self::new(2..=factors.len(), center..center + 1, triple_orders, false, pythagorean_triples))
+    }
+
+    // Rebuild the cumulative products and powersets of each non-final term from
+    // their current triples so enumeration continues exactly where it stopped.
+    pub fn resume(checkpoint: Checkpoint, triple_orders: Vec<TripleOrder>, print_factors: bool, pythagorean_triples: impl Into<TriplesTable>) -> Result<Self, String> {
+        let pythagorean_triples = pythagorean_triples.into();
+        if checkpoint.num_triples != pythagorean_triples.len() {
+            return Err(format!("The checkpoint was taken with {} Pythagorean triples but {} were computed.", checkpoint.num_triples, pythagorean_triples.len()));
+        }
+
+        if checkpoint.search_range.end > MAX_CENTER + 1 {
+            return Err(format!("The checkpoint search range {:?} goes past the largest supported center {MAX_CENTER}.", checkpoint.search_range));
+        }
+
+        let mut composite_number = Self {
+            num_factors: checkpoint.num_factors,
+            non_final_terms: checkpoint.non_final_terms.iter().map(|_| NonFinalTerm::new(0)).collect(),
+            final_terms: checkpoint.final_terms,
+            search_range: checkpoint.search_range,
+            search_interval: checkpoint.search_interval,
+            triple_orders,
+            print_factors,
+            pythagorean_triples,
+            temporary_buffer: TemporaryBuffer::default(),
+            checkpoint_path: None,
+            checkpoint_interval: Duration::MAX,
+            last_checkpoint: Instant::now(),
+            save_search_state: None,
+            checkpoint_error: None,
+            min_triples: 0,
+        };
+
+        for (i, state) in checkpoint.non_final_terms.iter().enumerate() {
+            let (previous_terms, next_terms) = composite_number.non_final_terms.split_at_mut(i);
+            let previous_term = previous_terms.last();
+            let current_term = next_terms.first_mut().unwrap();
+
+            if state.next_index > state.end_index || state.end_index > composite_number.pythagorean_triples.len() {
+                return Err(format!("The checkpoint term {i} goes past the {} computed Pythagorean triples.", composite_number.pythagorean_triples.len()));
+            }
+
+            current_term.current_triple = state.current_triple;
+            current_term.next_index = state.next_index;
+            current_term.end_index = state.end_index;
+
+            let c = state.current_triple.2;
+            if c == 1 { continue; } // This term is not in use for the current number of factors.
+
+            if composite_number.pythagorean_triples.c_values().get(state.next_index.wrapping_sub(1)) != Some(&c) {
+                return Err(format!("The checkpoint term {i} does not match the computed Pythagorean triples."));
+            }
+
+            let Some(product) = previous_term.map_or(1, |t| t.cumulative_product).checked_mul(c) else {
+                return Err(format!("The checkpoint term {i} overflows the product of its factors."));
+            };
+
+            current_term.cumulative_product = product;
+            (current_term.exponent, current_term.square_divisors) = square_divisors(previous_term, c);
+            Self::update_triples_powerset(&mut current_term.triples_powerset, current_term.current_triple, previous_term.map(|t| &t.triples_powerset));
+            current_term.triples_powerset.sort_and_dedup_by_c_and_a(&mut composite_number.temporary_buffer);
+        }
+
+        // The final terms are primes, at least the last non-final term and at
+        // most the largest center the non-final terms leave room for.
+        let final_terms = &composite_number.final_terms;
+        if !final_terms.is_empty() {
+            let Some(last_term) = composite_number.non_final_terms.iter().rev().find(|t| t.current_triple.2 != 1) else {
+                return Err("The checkpoint has final terms but no non-final terms.".to_string());
+            };
+
+            let max_final_term = (composite_number.search_range.end - 1) / last_term.cumulative_product;
+            if *final_terms.start() < last_term.current_triple.2 || *final_terms.end() > max_final_term {
+                return Err(format!("The checkpoint final terms {final_terms:?} are outside the range for the non-final terms."));
+            }
+        }
+
+        Ok(composite_number)
+    }
+
+    pub fn checkpoint_to(&mut self, path: PathBuf, interval: Duration) {
+        self.checkpoint_path = Some(path);
+        self.checkpoint_interval = interval;
+        self.last_checkpoint = Instant::now();
+    }
+
+    // Lets the caller add its own state, e.g. the patterns being checked, to
+    // each checkpoint before it is written.
+    pub fn save_in_checkpoints(&mut self, save_search_state: impl Fn(&mut Checkpoint) + 'static) {
+        self.save_search_state = Some(Box::new(save_search_state));
+    }
+
+    // Skip the centers with fewer magic triples than any of the checkers need.
+    pub fn skip_centers_below(&mut self, min_triples: usize) {
+        self.min_triples = min_triples;
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            num_triples: self.pythagorean_triples.len(),
+            num_factors: self.num_factors.clone(),
+            search_range: self.search_range.clone(),
+            search_interval: self.search_interval,
+            non_final_terms: self.non_final_terms.iter().map(|t| TermState { current_triple: t.current_triple, next_index: t.next_index, end_index: t.end_index }).collect(),
+            final_terms: self.final_terms.clone(),
+            search_modes: vec![],
+            min_squares: 0,
+            near_misses: vec![],
+            primitives: vec![],
+            held_back: vec![],
+        }
+    }
+
+    fn write_checkpoint(&mut self) -> Result<(), String> {
+        let Some(path) = &self.checkpoint_path else { return Ok(()) };
+
+        let mut checkpoint = self.checkpoint();
+        if let Some(save_search_state) = &self.save_search_state { save_search_state(&mut checkpoint); }
+
+        checkpoint.write(path).map_err(|error| format!("Failed to write the checkpoint to {}: {error}", path.display()))?;
+        self.last_checkpoint = Instant::now();
+        Ok(())
+    }
+
+    // Calls finish_range after each search range, before its checkpoint. Stops
+    // with an error before a range whose non-final terms the table can't cover,
+    // or as soon as a checkpoint can't be written.
+    pub fn for_each(&mut self, callback: impl Fn(&MagicTriples) + Send + Sync, mut finish_range: impl FnMut(&Range<u64>)) -> Result<(), String> {
+        while !self.search_range.is_empty() {
+            self.check_table_covers_non_final_terms()?;
+
+            eprintln!("Searching composite numbers with {:?} prime factors in the range {:?}.", self.num_factors, self.search_range);
             self.for_each_in_search_range(&callback);
+            if let Some(message) = self.checkpoint_error.take() { return Err(message); }
+            finish_range(&self.search_range);
+
+            self.next_search_range();
+            self.write_checkpoint()?;
+        }
+
+        eprintln!("Reached the largest supported center {MAX_CENTER}.");
+        Ok(())
+    }
+
+    fn next_search_range(&mut self) {
+        self.search_range.start = self.search_range.end;
+        self.search_range.end = self.search_range.end.saturating_add(self.search_interval).min(MAX_CENTER + 1);
+
+        self.non_final_terms.iter_mut().for_each(|t| t.reset(self.pythagorean_triples.len()));
+        self.final_terms = NO_FINAL_TERMS;
+
+        self.next_non_final_term(self.num_factors.end() - self.num_factors.start());
+    }
+
+    // Every non-final term is at most the final term so it is at most the
+    // square root of the end of the range. The table has to hold every
+    // Pythagorean prime up to there, otherwise centers would be skipped without
+    // any warning. The final terms are computed for each range separately.
+    fn check_table_covers_non_final_terms(&self) -> Result<(), String> {
+        let max_non_final_term = (self.search_range.end - 1).isqrt();
+        let largest_prime = self.pythagorean_triples.c_values().last().copied().unwrap_or(0);
+
+        if largest_prime >= max_non_final_term { return Ok(()); }
+        Err(format!("The search range {:?} needs the Pythagorean primes up to {max_non_final_term} but the largest of the {} triples is for {largest_prime}. Use a larger --num-triples or --triples-cache.", self.search_range, self.pythagorean_triples.len()))
+    }
 
-            self.search_range.start = self.search_range.end;
-            self.search_range.end += crate::SEARCH_INTERVAL;
+    // The final terms in the window, taken from the table if it covers them and
+    // otherwise computed for just this window so the table never has to grow.
+    fn final_term_triples(&self, window: Range<u64>) -> PythagoreanTriples {
+        let c_values = self.pythagorean_triples.c_values();
+        if c_values.last().is_none_or(|&largest_prime| largest_prime < window.end - 1) { return PythagoreanTriples::for_window(window); }
 
-            self.non_final_terms.iter_mut().for_each(|t| t.reset(self.pythagorean_triples.len()));
-            self.next_non_final_term(self.num_factors.end() - self.num_factors.start());
+        let indexes = c_values.partition_point(|&c| c < window.start)..c_values.partition_point(|&c| c < window.end);
 
-            self.final_term_start_index = 0;
-            self.final_term_end_index = 0;
+        PythagoreanTriples {
+            a_values: self.pythagorean_triples.a_values()[indexes.clone()].to_vec(),
+            b_values: self.pythagorean_triples.b_values()[indexes.clone()].to_vec(),
+            c_values: c_values[indexes].to_vec(),
+            factors: vec![],
         }
     }
 
-    fn for_each_in_search_range<F: Fn(usize, &mut Vec<u64>, &mut Vec<u64>, u64) + Send + Sync>(&mut self, callback: F) {
-        loop {
-            if crate::PRINT_FACTORS {
-                let first_prime = self.pythagorean_triples.c_values.get(self.final_term_start_index);
-                let last_prime = self.pythagorean_triples.c_values.get(self.final_term_end_index - 1);
+    pub fn for_each_in_search_range<F: Fn(&MagicTriples) + Send + Sync>(&mut self, callback: F) {
+        self.check_final_terms(callback, true);
+    }
+
+    #[cfg(test)]
+    fn for_each_final_term<F: Fn(&MagicTriples) + Send + Sync>(&mut self, callback: F) {
+        self.check_final_terms(callback, false);
+    }
 
-                if let (Some(first_prime), Some(last_prime)) = (first_prime, last_prime) {
-                    self.non_final_terms.iter().for_each(|t| print!("{} x ", t.current_triple.2));
-                    println!("pythagorean_primes({:?})", first_prime..=last_prime);
+    // Queue the final terms after each of the non-final terms in the search
+    // range, or just the current ones, and return once they've all been checked.
+    fn check_final_terms<F: Fn(&MagicTriples) + Send + Sync>(&mut self, callback: F, whole_range: bool) {
+        let triple_orders = self.triple_orders.clone();
+        let scheduler = Scheduler::new(SCHEDULER_WINDOW, |final_term| Self::check_final_term(final_term, &triple_orders, &callback));
+
+        rayon::in_place_scope_fifo(|scope| {
+            loop {
+                if self.print_factors && !self.final_terms.is_empty() {
+                    self.non_final_terms.iter().for_each(|t| eprint!("{} x ", t.current_triple.2));
+                    eprintln!("pythagorean_primes({:?})", self.final_terms);
                 }
+
+                self.queue_final_terms(&scheduler, scope);
+                if !whole_range || self.checkpoint_error.is_some() || !self.next_available_term() { break; }
             }
 
-            self.for_each_final_term(&callback);
-            if !self.next_available_term() { break; }
-        }
+            scheduler.flush(scope);
+        });
     }
 
     fn next_available_term(&mut self) -> bool {
@@ -91,18 +346,19 @@ impl CompositeNumber {
         let current_term = next_terms.first_mut().unwrap();
 
         if current_term.next_index < current_term.end_index {
-            let c = self.pythagorean_triples.c_values[current_term.next_index];
-            let mut product = previous_product * c;
+            let c = self.pythagorean_triples.c_values()[current_term.next_index];
+            let Some(mut product) = previous_product.checked_mul(c) else { return false };
 
             let mut next_max = Self::max_value_for_term(term_index + 1, num_terms, product, max_value);
             if next_max < c { return false; }
 
-            let a = self.pythagorean_triples.a_values[current_term.next_index];
-            let b = self.pythagorean_triples.b_values[current_term.next_index];
+            let a = self.pythagorean_triples.a_values()[current_term.next_index];
+            let b = self.pythagorean_triples.b_values()[current_term.next_index];
             let f = if c == previous_c { previous_f } else { previous_f + 1 };
 
             current_term.current_triple = (a, b, c, f);
             current_term.cumulative_product = product;
+            (current_term.exponent, current_term.square_divisors) = square_divisors(previous_term, c);
             Self::update_triples_powerset(&mut current_term.triples_powerset, current_term.current_triple, previous_powerset);
             current_term.triples_powerset.sort_and_dedup_by_c_and_a(&mut self.temporary_buffer);
             current_term.next_index += 1;
@@ -113,22 +369,22 @@ impl CompositeNumber {
                 let previous_powerset = previous_terms.last().map(|t| &t.triples_powerset);
                 let next_term = next_terms.first_mut().unwrap();
 
-                product *= c;
+                product = product.checked_mul(c).expect("The next_max bound should prevent the product from overflowing.");
 
+                (next_term.exponent, next_term.square_divisors) = square_divisors(previous_terms.last(), c);
                 next_term.current_triple = (a, b, c, f);
                 next_term.cumulative_product = product;
                 Self::update_triples_powerset(&mut next_term.triples_powerset, next_term.current_triple, previous_powerset);
                 next_term.triples_powerset.sort_and_dedup_by_c_and_a(&mut self.temporary_buffer);
                 next_term.next_index = next_index;
-                next_term.end_index = self.pythagorean_triples.c_values.partition_point(|&c| c <= next_max);
+                next_term.end_index = self.pythagorean_triples.c_values().partition_point(|&c| c <= next_max);
 
                 next_max = Self::max_value_for_term(i + 1, num_terms, product, max_value);
             }
 
             let next_min = c.max(self.search_range.start.div_ceil(product));
 
-            self.final_term_end_index = self.pythagorean_triples.c_values.partition_point(|&c| c <= next_max);
-            self.final_term_start_index = self.pythagorean_triples.c_values[..self.final_term_end_index].partition_point(|&c| c < next_min);
+            self.final_terms = next_min..=next_max;
 
             true
         } else {
@@ -136,43 +392,118 @@ impl CompositeNumber {
         }
     }
 
-    fn for_each_final_term<F: Fn(usize, &mut Vec<u64>, &mut Vec<u64>, u64) + Send + Sync>(&mut self, callback: F) {
+    // The checkers are quadratic in the number of magic triples so that's
+    // squared to estimate how long each center will take.
+    fn queue_final_terms<'s, F: Fn(FinalTerm) + Sync>(&mut self, scheduler: &'s Scheduler<FinalTerm, F>, scope: &ScopeFifo<'s>) {
+        if self.final_terms.is_empty() { return; }
+
         let previous_term = self.non_final_terms.last().unwrap();
-        let (previous_product, previous_c, previous_f) = (previous_term.cumulative_product, previous_term.current_triple.2, previous_term.current_triple.3);
+        let (previous_a, previous_b, previous_c, _) = previous_term.current_triple;
+        let new_prime_divisors = previous_term.square_divisors * 3;
+        let (_, repeated_prime_divisors) = square_divisors(Some(previous_term), previous_c);
+
+        let non_final_terms = Arc::new(NonFinalTerms {
+            product: previous_term.cumulative_product,
+            last_triple: previous_term.current_triple,
+            triples_powerset: previous_term.triples_powerset.clone(),
+            factors: self.non_final_terms.iter().map(|t| t.current_triple.2).filter(|&c| c != 1).collect(),
+        });
 
-        let a_values = &self.pythagorean_triples.a_values[self.final_term_start_index..self.final_term_end_index];
-        let b_values = &self.pythagorean_triples.b_values[self.final_term_start_index..self.final_term_end_index];
-        let c_values = &self.pythagorean_triples.c_values[self.final_term_start_index..self.final_term_end_index];
-        let mut triples = a_values.iter().zip(b_values).zip(c_values).map(|((a, b), c)| (*a, *b, *c));
+        // Every new prime gives the center the same number of triples, so if
+        // that's too few then only the final term that repeats the last prime
+        // can be used and the rest of the range is skipped without computing it.
+        if num_magic_triples(new_prime_divisors) < self.min_triples {
+            let num_triples = num_magic_triples(repeated_prime_divisors);
 
-        thread_local! {
-            static STATE: RefCell<(PythagoreanTriples, TemporaryBuffer)> = RefCell::new((PythagoreanTriples::new(0), TemporaryBuffer::default()));
+            if self.final_terms.contains(&previous_c) && num_triples >= self.min_triples {
+                scheduler.push(scope, (num_triples as u64).pow(2), FinalTerm { non_final_terms, triple: (previous_a, previous_b, previous_c) });
+            }
+
+            self.final_terms = NO_FINAL_TERMS;
+            return;
         }
 
-        loop {
-            self.triples_buffer.clear();
-            self.triples_buffer.extend(triples.by_ref().take(self.triples_buffer.capacity()));
-            if self.triples_buffer.is_empty() { break; }
+        while !self.final_terms.is_empty() {
+            let (window_start, max_final_term) = (*self.final_terms.start(), *self.final_terms.end());
+            let window = window_start..max_final_term.min(window_start.saturating_add(FINAL_TERM_WINDOW - 1)) + 1;
+            let final_terms = self.final_term_triples(window.clone());
 
-            self.triples_buffer.par_iter().for_each(|&(a, b, c)| {
-                let f = if c == previous_c { previous_f } else { previous_f + 1 };
-                let final_product = previous_product * c;
+            for i in 0..final_terms.len() {
+                let triple = (final_terms.a_values[i], final_terms.b_values[i], final_terms.c_values[i]);
+                let divisors = if triple.2 == previous_c { repeated_prime_divisors } else { new_prime_divisors };
 
-                STATE.with_borrow_mut(|(current_powerset, temporary_buffer)| {
-                    Self::update_triples_powerset(current_powerset, (a, b, c, f), Some(&previous_term.triples_powerset));
+                let num_triples = num_magic_triples(divisors);
+                if num_triples < self.min_triples { continue; }
 
-                    current_powerset.remove_trivial(temporary_buffer);
-                    current_powerset.into_magic_triples(final_product);
+                scheduler.push(scope, (num_triples as u64).pow(2), FinalTerm { non_final_terms: Arc::clone(&non_final_terms), triple });
+            }
 
-                    let primitive_start = match crate::SEARCH_MODE {
-                        SearchMode::Patterns16 => current_powerset.sort_and_dedup_by_primitive_and_a(temporary_buffer),
-                        SearchMode::Patterns234 => { current_powerset.sort_and_dedup_by_a(temporary_buffer); 0 }
-                    };
+            // Only record progress once everything queued so far has been checked.
+            self.final_terms = window.end..=max_final_term;
+            if self.last_checkpoint.elapsed() >= self.checkpoint_interval {
+                scheduler.wait_until_idle(scope);
+                if let Err(message) = self.write_checkpoint() { self.checkpoint_error = Some(message); return; }
+            }
+        }
+    }
 
-                    callback(primitive_start, &mut current_powerset.a_values, &mut current_powerset.b_values, final_product);
-                });
+    fn check_final_term(final_term: FinalTerm, triple_orders: &[TripleOrder], callback: &impl Fn(&MagicTriples)) {
+        thread_local! {
+            static STATE: RefCell<(PythagoreanTriples, TemporaryBuffer, Vec<u64>)> = RefCell::new((PythagoreanTriples::new(0), TemporaryBuffer::default(), vec![]));
+        }
+
+        let NonFinalTerms { product, last_triple: (_, _, previous_c, previous_f), triples_powerset, factors: non_final_factors } = &*final_term.non_final_terms;
+        let (a, b, c) = final_term.triple;
+
+        let f = if c == *previous_c { *previous_f } else { previous_f + 1 };
+        let final_product = product.checked_mul(c).expect("The final term bound should prevent the product from overflowing.");
+
+        // The checkers can split a large center across threads, and this thread
+        // can pick up another final term while it waits, so the buffers are
+        // taken rather than borrowed.
+        let (mut current_powerset, mut temporary_buffer, mut factors) = STATE.take();
+        Self::update_triples_powerset(&mut current_powerset, (a, b, c, f), Some(triples_powerset));
+
+        current_powerset.remove_trivial(&mut temporary_buffer);
+        current_powerset.into_magic_triples(final_product);
+
+        factors.clear();
+        factors.extend_from_slice(non_final_factors);
+        factors.push(c);
+
+        for &order in triple_orders {
+            let primitive_start = match order {
+                TripleOrder::ByPrimitiveAndA => current_powerset.sort_and_dedup_by_primitive_and_a(&mut temporary_buffer),
+                TripleOrder::ByA => { current_powerset.sort_and_dedup_by_a(&mut temporary_buffer); 0 }
+            };
+
+            callback(&MagicTriples {
+                order,
+                primitive_start,
+                a_values: &current_powerset.a_values,
+                b_values: &current_powerset.b_values,
+                center: final_product,
+                factors: &factors,
             });
         }
+
+        STATE.set((current_powerset, temporary_buffer, factors));
+    }
+
+    pub fn prime_factors(mut n: u64) -> Vec<u64> {
+        let mut factors = vec![];
+        let mut divisor = 2;
+
+        while divisor <= n / divisor {
+            while n.is_multiple_of(divisor) {
+                factors.push(divisor);
+                n /= divisor;
+            }
+            divisor += if divisor == 2 { 1 } else { 2 };
+        }
+
+        if n > 1 { factors.push(n); }
+        factors
     }
 
     fn max_value_for_term(term_index: usize, num_terms: usize, previous_product: u64, max_value: u64) -> u64 {
@@ -182,10 +513,21 @@ impl CompositeNumber {
         match remaining_terms {
             1 => remaining_multiple,
             2 => remaining_multiple.isqrt(),
-            _ => (remaining_multiple as f64).powf(1. / remaining_terms as f64).floor() as u64,
+            _ => Self::integer_root(remaining_multiple, remaining_terms as u32),
         }
     }
 
+    // The floating point root can be off by one for large numbers so correct it
+    // to make sure that the product of the remaining terms can't overflow.
+    fn integer_root(n: u64, k: u32) -> u64 {
+        let mut root = (n as f64).powf(1. / k as f64).floor() as u64;
+
+        while root.checked_pow(k).is_none_or(|power| power > n) { root -= 1; }
+        while (root + 1).checked_pow(k).is_some_and(|power| power <= n) { root += 1; }
+
+        root
+    }
+
     fn update_triples_powerset(current_powerset: &mut PythagoreanTriples, current_triple: (u64, u64, u64, u32), previous_powerset: Option<&PythagoreanTriples>) {
         current_powerset.clear();
         current_powerset.push(current_triple);
@@ -203,15 +545,39 @@ impl CompositeNumber {
 
     #[cfg(test)]
     fn final_factors(&self) -> Vec<u64> {
-        self.pythagorean_triples.c_values[self.final_term_start_index..self.final_term_end_index].to_vec()
+        self.final_term_triples(*self.final_terms.start()..*self.final_terms.end() + 1).c_values
     }
 }
 
+// The exponent of c and the number of divisors of the square of the product
+// after multiplying the previous term by the prime c. The primes are in
+// increasing order so c is either a new prime or the previous one again.
+fn square_divisors(previous_term: Option<&NonFinalTerm>, c: u64) -> (u32, u64) {
+    let (previous_c, exponent, divisors) = previous_term.map_or((1, 0, 1), |t| (t.current_triple.2, t.exponent, t.square_divisors));
+
+    if c == previous_c {
+        (exponent + 1, divisors / (2 * exponent as u64 + 1) * (2 * exponent as u64 + 3))
+    } else {
+        (1, divisors * 3)
+    }
+}
+
+// Every prime factor of the center is 1 mod 4 so the number of ways to write c²
+// as x² + y² with x > 0 and y ≥ 0 is the number of divisors of c², which is the
+// product of 2e + 1 over the exponents e of its primes. Leaving out c² + 0²,
+// they come in pairs (x, y) and (y, x) and each pair is the magic triple
+// ((x + y)², c², (x - y)²).
+pub fn num_magic_triples(square_divisors: u64) -> usize {
+    (square_divisors as usize).saturating_sub(1) / 2
+}
+
 impl NonFinalTerm {
     fn new(num_triples: usize) -> Self {
         Self {
             current_triple: (0, 0, 1, 0),
             cumulative_product: 1,
+            exponent: 0,
+            square_divisors: 1,
             triples_powerset: PythagoreanTriples::new(0),
             next_index: 0,
             end_index: num_triples,
@@ -221,6 +587,8 @@ impl NonFinalTerm {
     pub fn reset(&mut self, num_triples: usize) {
         self.current_triple = (0, 0, 1, 0);
         self.cumulative_product = 1;
+        self.exponent = 0;
+        self.square_divisors = 1;
         self.triples_powerset.clear();
         self.next_index = 0;
         self.end_index = num_triples;
@@ -235,7 +603,7 @@ mod test {
     #[test]
     fn it_can_advance_through_each_non_final_term_ensuring_lexical_ordering() {
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
         assert_eq!(composite_number.non_final_factors(), &[1, 5]);
 
         composite_number.next_non_final_term(1);
@@ -249,9 +617,10 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn it_returns_false_when_the_search_range_has_been_exhausted() {
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
         assert_eq!(composite_number.non_final_factors(), &[1, 5]);
         assert!(5 * 5 < 1000);
 
@@ -283,7 +652,7 @@ mod test {
     #[test]
     fn it_sets_the_current_triple_of_each_non_final_term() {
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
 
         assert_eq!(composite_number.non_final_factors(), &[1, 5]);
         assert_eq!(composite_number.non_final_terms[0].current_triple, (0, 0, 1, 0));
@@ -308,7 +677,7 @@ mod test {
     #[test]
     fn it_calculates_the_cumulative_product_of_each_non_final_term() {
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
 
         assert_eq!(composite_number.non_final_factors(), &[1, 5]);
         assert_eq!(composite_number.non_final_terms[0].cumulative_product, 1);
@@ -333,7 +702,7 @@ mod test {
     #[test]
     fn it_computes_the_triples_powerset_for_each_non_final_term() {
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
 
         assert_eq!(composite_number.non_final_factors(), &[1, 5]);
         assert_eq!(composite_number.non_final_terms[1].triples_powerset.a_values, &[3]);
@@ -370,7 +739,7 @@ mod test {
     #[test]
     fn it_can_fully_exhaust_the_search_range() {
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=4, 485..1000, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=4, 485..1000, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
         assert_eq!(composite_number.non_final_factors(), &[1, 1, 5]);
         assert_eq!(composite_number.final_factors(), &[97, 101, 109, 113, 137, 149, 157, 173, 181, 193, 197]);
 
@@ -403,17 +772,227 @@ mod test {
         assert_eq!(composite_number.final_factors(), &[5]);
     }
 
+    fn all_centers(composite_number: &mut CompositeNumber) -> Vec<u64> {
+        let centers = Mutex::new(vec![]);
+        composite_number.for_each_in_search_range(|triples| centers.lock().unwrap().push(triples.center));
+
+        let mut centers = centers.into_inner().unwrap();
+        centers.sort();
+        centers
+    }
+
     #[test]
-    fn it_can_enumerate_all_final_terms_in_the_search_range_and_yield_magic_triples_patterns16() {
-        if !matches!(crate::SEARCH_MODE, SearchMode::Patterns16) { return; }
+    fn it_searches_the_first_composite_number_of_each_search_range() {
+        let mut composite_number = CompositeNumber::new(2..=3, 0..500, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100));
+        let mut centers = all_centers(&mut composite_number);
+
+        composite_number.next_search_range();
+        assert_eq!(composite_number.search_range, 500..1000);
+        centers.extend(all_centers(&mut composite_number));
+
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100));
+        assert_eq!(centers, all_centers(&mut composite_number));
+        assert!(centers.contains(&(5 * 197)));
+    }
+
+    #[test]
+    fn it_computes_the_final_terms_past_the_end_of_the_table() {
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(10));
+        assert_eq!(composite_number.pythagorean_triples.c_values().last(), Some(&89));
+        assert_eq!(composite_number.final_factors().last(), Some(&197));
+
+        let mut sufficient = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100));
+        assert_eq!(all_centers(&mut composite_number), all_centers(&mut sufficient));
+        assert_eq!(composite_number.pythagorean_triples.len(), 10);
+    }
+
+    #[test]
+    fn it_keeps_the_table_the_same_length_across_search_ranges() {
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(10));
+        let mut sufficient = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(200));
+
+        for _ in 0..5 {
+            assert_eq!(all_centers(&mut composite_number), all_centers(&mut sufficient));
+            assert_eq!(composite_number.pythagorean_triples.len(), 10);
+
+            composite_number.next_search_range();
+            sufficient.next_search_range();
+        }
+        assert_eq!(composite_number.search_range, 5000..6000);
+    }
+
+    #[test]
+    fn it_skips_the_final_terms_of_a_prefix_without_computing_them_when_none_have_enough_triples() {
+        let mut composite_number = CompositeNumber::new(2..=2, 0..1_000_000_000_000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(40_000));
+        composite_number.skip_centers_below(5);
+
+        // Each final range has up to 2 x 10^11 candidates so computing them would not finish.
+        assert!(all_centers(&mut composite_number).is_empty());
+        assert!(composite_number.final_terms.is_empty());
+    }
+
+    #[test]
+    fn it_stops_with_an_error_when_the_table_cannot_cover_the_non_final_terms() {
+        let mut composite_number = CompositeNumber::new(2..=3, 0..100_000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(10));
+        let centers = Mutex::new(vec![]);
+
+        let result = composite_number.for_each(|triples| centers.lock().unwrap().push(triples.center), |_| {});
+        assert!(result.is_err_and(|message| message.contains("up to 316")));
+        assert!(centers.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_stops_with_an_error_when_a_checkpoint_cannot_be_written() {
+        let path = std::env::temp_dir().join(format!("magic_square_of_squares_{}_missing", std::process::id())).join("search.checkpoint");
+        let finished_ranges = Mutex::new(vec![]);
+
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100));
+        composite_number.checkpoint_to(path.clone(), Duration::ZERO);
+
+        let result = composite_number.for_each(|_| {}, |range| finished_ranges.lock().unwrap().push(range.clone()));
+        assert!(result.is_err_and(|message| message.starts_with("Failed to write the checkpoint")));
+        assert!(finished_ranges.into_inner().unwrap().is_empty());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn it_can_resume_from_a_checkpoint_without_rechecking_or_skipping_centers() {
+        let centers = |composite_number: &mut CompositeNumber, search_all: bool| {
+            let centers = Mutex::new(vec![]);
+            let callback = |triples: &MagicTriples| centers.lock().unwrap().push(triples.center);
+
+            if search_all { composite_number.for_each_in_search_range(callback); } else { composite_number.for_each_final_term(callback); }
+            centers.into_inner().unwrap()
+        };
+
+        let mut composite_number = CompositeNumber::new(2..=4, 0..5000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(300));
+        let mut all_centers = centers(&mut composite_number, true);
+        all_centers.sort();
+
+        let mut composite_number = CompositeNumber::new(2..=4, 0..5000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(300));
+        let mut checked_centers = centers(&mut composite_number, false);
+        assert!(composite_number.next_available_term());
+        checked_centers.extend(centers(&mut composite_number, false));
+        assert!(composite_number.next_available_term());
+
+        // Skip one final term as if the process stopped after checking it.
+        let first_final_term = composite_number.final_factors()[0];
+        checked_centers.push(composite_number.non_final_terms.last().unwrap().cumulative_product * first_final_term);
+        composite_number.final_terms = first_final_term + 1..=*composite_number.final_terms.end();
+
+        let checkpoint = composite_number.checkpoint();
+        let mut resumed = CompositeNumber::resume(checkpoint, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(300)).unwrap();
+        assert_eq!(resumed.non_final_factors(), composite_number.non_final_factors());
+        assert_eq!(resumed.final_factors(), composite_number.final_factors());
+
+        for (resumed_term, term) in resumed.non_final_terms.iter().zip(composite_number.non_final_terms.iter()) {
+            assert_eq!(resumed_term.cumulative_product, term.cumulative_product);
+            assert_eq!((resumed_term.exponent, resumed_term.square_divisors), (term.exponent, term.square_divisors));
+            assert_eq!(resumed_term.triples_powerset.a_values, term.triples_powerset.a_values);
+            assert_eq!(resumed_term.triples_powerset.b_values, term.triples_powerset.b_values);
+            assert_eq!(resumed_term.triples_powerset.factors, term.triples_powerset.factors);
+        }
+
+        checked_centers.extend(centers(&mut resumed, true));
+        checked_centers.sort();
+        assert_eq!(checked_centers, all_centers);
+    }
+
+    #[test]
+    fn it_refuses_to_resume_with_a_different_triples_table() {
+        let composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100));
+        let checkpoint = composite_number.checkpoint();
+
+        assert!(CompositeNumber::resume(checkpoint, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(50)).is_err());
+    }
+
+    #[test]
+    fn it_refuses_to_resume_from_positions_outside_the_triples_table() {
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100));
+        assert!(composite_number.next_available_term());
+        let resume = |checkpoint| CompositeNumber::resume(checkpoint, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100));
+        assert!(resume(composite_number.checkpoint()).is_ok());
+
+        let mut checkpoint = composite_number.checkpoint();
+        checkpoint.non_final_terms[0].end_index = 101;
+        assert!(resume(checkpoint).is_err());
+
+        let mut checkpoint = composite_number.checkpoint();
+        checkpoint.non_final_terms[0].next_index = checkpoint.non_final_terms[0].end_index + 1;
+        assert!(resume(checkpoint).is_err());
+
+        let mut checkpoint = composite_number.checkpoint();
+        checkpoint.final_terms = *checkpoint.final_terms.start()..=1000;
+        assert!(resume(checkpoint).is_err());
+
+        let mut checkpoint = composite_number.checkpoint();
+        checkpoint.final_terms = 1..=*checkpoint.final_terms.end();
+        assert!(resume(checkpoint).is_err());
+    }
+
+    #[test]
+    fn it_yields_the_prime_factors_of_each_composite_number() {
+        let pythagorean_triples = PythagoreanTriples::new(100);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..150, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
 
+        let callbacks = Mutex::new(vec![]);
+        composite_number.for_each_in_search_range(|triples| {
+            callbacks.lock().unwrap().push((triples.center, triples.factors.to_vec()))
+        });
+
+        let mut callbacks = callbacks.into_inner().unwrap();
+        callbacks.sort();
+
+        assert_eq!(callbacks, vec![(25, vec![5, 5]), (65, vec![5, 13]), (85, vec![5, 17]), (125, vec![5, 5, 5]), (145, vec![5, 29])]);
+    }
+
+    #[test]
+    fn it_computes_exact_integer_roots() {
+        assert_eq!(CompositeNumber::integer_root(124, 3), 4);
+        assert_eq!(CompositeNumber::integer_root(125, 3), 5);
+        assert_eq!(CompositeNumber::integer_root(u64::MAX, 3), 2_642_245);
+        assert_eq!(CompositeNumber::integer_root(u64::MAX, 27), 5);
+        assert_eq!(CompositeNumber::integer_root(5u64.pow(27) - 1, 27), 4);
+    }
+
+    #[test]
+    fn it_stops_searching_at_the_largest_supported_center() {
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..150, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, MAX_CENTER - 1000..MAX_CENTER + 1000, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
+        assert_eq!(composite_number.search_range, MAX_CENTER - 1000..MAX_CENTER + 1);
+
+        assert!(all_centers(&mut composite_number).iter().all(|&c| c <= MAX_CENTER));
+
+        composite_number.next_search_range();
+        assert!(composite_number.search_range.is_empty());
+    }
+
+    #[test]
+    fn it_refuses_to_resume_past_the_largest_supported_center() {
+        let composite_number = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100));
+        let mut checkpoint = composite_number.checkpoint();
+        checkpoint.search_range = u64::MAX - 1000..u64::MAX;
+
+        assert!(CompositeNumber::resume(checkpoint, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(100)).is_err());
+    }
+
+    #[test]
+    fn it_can_factorize_a_center() {
+        assert_eq!(CompositeNumber::prime_factors(425), &[5, 5, 17]);
+        assert_eq!(CompositeNumber::prime_factors(4_294_967_311 * 5), &[5, 4_294_967_311]);
+        assert_eq!(CompositeNumber::prime_factors(97), &[97]);
+        assert_eq!(CompositeNumber::prime_factors(1), &[] as &[u64]);
+    }
+
+    #[test]
+    fn it_can_enumerate_all_final_terms_in_the_search_range_and_yield_magic_triples_patterns16() {
+        let pythagorean_triples = PythagoreanTriples::new(100);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..150, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
         assert_eq!(composite_number.non_final_factors(), &[1, 5]);
 
         let callbacks = Mutex::new(vec![]);
-        composite_number.for_each_final_term(|primitive_start, a_values, b_values, c| {
-            callbacks.lock().unwrap().push((primitive_start, a_values.to_vec(), b_values.to_vec(), c))
+        composite_number.for_each_final_term(|triples| {
+            callbacks.lock().unwrap().push((triples.primitive_start, triples.a_values.to_vec(), triples.b_values.to_vec(), triples.center))
         });
 
         let mut callbacks = callbacks.into_inner().unwrap();
@@ -429,15 +1008,13 @@ mod test {
 
     #[test]
     fn it_can_enumerate_all_final_terms_in_the_search_range_and_yield_magic_triples_patterns234() {
-        if !matches!(crate::SEARCH_MODE, SearchMode::Patterns234) { return; }
-
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..150, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..150, vec![TripleOrder::ByA], false, pythagorean_triples);
         assert_eq!(composite_number.non_final_factors(), &[1, 5]);
 
         let callbacks = Mutex::new(vec![]);
-        composite_number.for_each_final_term(|primitive_start, a_values, b_values, c| {
-            callbacks.lock().unwrap().push((primitive_start, a_values.to_vec(), b_values.to_vec(), c))
+        composite_number.for_each_final_term(|triples| {
+            callbacks.lock().unwrap().push((triples.primitive_start, triples.a_values.to_vec(), triples.b_values.to_vec(), triples.center))
         });
 
         let mut callbacks = callbacks.into_inner().unwrap();
@@ -453,14 +1030,12 @@ mod test {
 
     #[test]
     fn it_can_enumerate_all_composite_numbers_in_the_search_range_and_yield_magic_triples_patterns16() {
-        if !matches!(crate::SEARCH_MODE, SearchMode::Patterns16) { return; }
-
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..150, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..150, vec![TripleOrder::ByPrimitiveAndA], false, pythagorean_triples);
 
         let callbacks = Mutex::new(vec![]);
-        composite_number.for_each_in_search_range(|primitive_start, a_values, b_values, c| {
-            callbacks.lock().unwrap().push((primitive_start, a_values.to_vec(), b_values.to_vec(), c))
+        composite_number.for_each_in_search_range(|triples| {
+            callbacks.lock().unwrap().push((triples.primitive_start, triples.a_values.to_vec(), triples.b_values.to_vec(), triples.center))
         });
 
         let mut callbacks = callbacks.into_inner().unwrap();
@@ -479,14 +1054,12 @@ mod test {
 
     #[test]
     fn it_can_enumerate_all_composite_numbers_in_the_search_range_and_yield_magic_triples_patterns234() {
-        if !matches!(crate::SEARCH_MODE, SearchMode::Patterns234) { return; }
-
         let pythagorean_triples = PythagoreanTriples::new(100);
-        let mut composite_number = CompositeNumber::new(2..=3, 0..150, pythagorean_triples);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..150, vec![TripleOrder::ByA], false, pythagorean_triples);
 
         let callbacks = Mutex::new(vec![]);
-        composite_number.for_each_in_search_range(|primitive_start, a_values, b_values, c| {
-            callbacks.lock().unwrap().push((primitive_start, a_values.to_vec(), b_values.to_vec(), c))
+        composite_number.for_each_in_search_range(|triples| {
+            callbacks.lock().unwrap().push((triples.primitive_start, triples.a_values.to_vec(), triples.b_values.to_vec(), triples.center))
         });
 
         let mut callbacks = callbacks.into_inner().unwrap();
@@ -502,4 +1075,61 @@ mod test {
         // These triples are for 5 x 5 x final_term.
         assert_eq!(callbacks[3], (0, vec![155, 161, 175], vec![85, 73, 25], 125));
     }
+
+    #[test]
+    fn it_yields_the_magic_triples_once_for_each_triple_order() {
+        let pythagorean_triples = PythagoreanTriples::new(100);
+        let mut composite_number = CompositeNumber::new(2..=3, 0..150, vec![TripleOrder::ByPrimitiveAndA, TripleOrder::ByA], false, pythagorean_triples);
+
+        let callbacks = Mutex::new(vec![]);
+        composite_number.for_each_in_search_range(|triples| {
+            callbacks.lock().unwrap().push((triples.center, triples.order, triples.primitive_start, triples.a_values.to_vec()))
+        });
+
+        let mut callbacks = callbacks.into_inner().unwrap();
+        callbacks.sort();
+        assert_eq!(callbacks.len(), 10);
+
+        assert_eq!(callbacks[2], (65, TripleOrder::ByPrimitiveAndA, 2, vec![85, 91, 79, 89]));
+        assert_eq!(callbacks[3], (65, TripleOrder::ByA, 0, vec![79, 85, 89, 91]));
+        assert_eq!(callbacks[6], (125, TripleOrder::ByPrimitiveAndA, 3, vec![155, 161, 175]));
+        assert_eq!(callbacks[7], (125, TripleOrder::ByA, 0, vec![155, 161, 175]));
+    }
+
+    fn centers_and_triples(composite_number: &mut CompositeNumber) -> Vec<(u64, usize)> {
+        let callbacks = Mutex::new(vec![]);
+        composite_number.for_each_in_search_range(|triples| callbacks.lock().unwrap().push((triples.center, triples.a_values.len())));
+
+        let mut callbacks = callbacks.into_inner().unwrap();
+        callbacks.sort();
+        callbacks
+    }
+
+    #[test]
+    fn it_predicts_the_number_of_magic_triples_from_the_exponents_of_the_center() {
+        let mut composite_number = CompositeNumber::new(2..=4, 0..20_000, vec![TripleOrder::ByA], false, PythagoreanTriples::new(1000));
+        let callbacks = centers_and_triples(&mut composite_number);
+        assert!(callbacks.len() > 100);
+
+        for (center, num_triples) in callbacks {
+            let factors = CompositeNumber::prime_factors(center);
+            let square_divisors = factors.chunk_by(|p, q| p == q).map(|run| 2 * run.len() as u64 + 1).product();
+            assert_eq!(num_magic_triples(square_divisors), num_triples, "{center}");
+        }
+    }
+
+    #[test]
+    fn it_skips_centers_with_fewer_magic_triples_than_needed() {
+        let mut composite_number = CompositeNumber::new(2..=4, 0..20_000, vec![TripleOrder::ByA], false, PythagoreanTriples::new(1000));
+        let all_centers = centers_and_triples(&mut composite_number);
+
+        for min_triples in [3, 4, 5, 13] {
+            let mut composite_number = CompositeNumber::new(2..=4, 0..20_000, vec![TripleOrder::ByA], false, PythagoreanTriples::new(1000));
+            composite_number.skip_centers_below(min_triples);
+
+            let expected = all_centers.iter().copied().filter(|&(_, num_triples)| num_triples >= min_triples).collect::<Vec<_>>();
+            assert!(expected.len() < all_centers.len());
+            assert_eq!(centers_and_triples(&mut composite_number), expected);
+        }
+    }
 }
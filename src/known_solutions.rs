@@ -0,0 +1,97 @@
+use crate::{MagicSquareCandidate, primitive_orientation};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// A catalogue of known squares that results are matched against so they can
+// be tagged with where they were published instead of being searched for
+// again. See known_solutions.txt for the format.
+
+const BUILTIN: &str = include_str!("../known_solutions.txt");
+
+#[derive(Debug, Default)]
+pub struct KnownSolutions {
+    notes: HashMap<[u128; 9], String>,
+}
+
+impl KnownSolutions {
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN).expect("The built-in known solutions are invalid")
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+        Self::parse(&text).map_err(|message| format!("{}: {message}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut notes = HashMap::new();
+
+        for (i, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            let mut cells = [0; 9];
+            let mut rest = line;
+
+            for cell in &mut cells {
+                let end = rest.find(|c: char| !c.is_ascii_digit() && c != '_').unwrap_or(rest.len());
+                *cell = rest[..end].replace('_', "").parse().map_err(|_| format!("Line {i} should start with nine cells."))?;
+                rest = rest[end..].trim_start().strip_prefix(',').unwrap_or(&rest[end..]).trim_start();
+            }
+
+            if cells.contains(&0) { return Err(format!("Line {i} has a zero cell.")); }
+            if rest.is_empty() { return Err(format!("Line {i} has no note on where the square comes from.")); }
+
+            notes.insert(primitive_orientation(cells), rest.to_string());
+        }
+
+        Ok(Self { notes })
+    }
+
+    pub fn find(&self, candidate: &MagicSquareCandidate) -> Option<&str> {
+        self.notes.get(&candidate.primitive_cells()).map(String::as_str)
+    }
+
+    // For tests that check every entry of the catalogue.
+    #[cfg(test)]
+    pub fn entries(&self) -> impl Iterator<Item = (&[u128; 9], &str)> {
+        self.notes.iter().map(|(cells, note)| (cells, note.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Pattern, SALLOWS};
+
+    #[test]
+    fn it_matches_known_squares_in_any_orientation_and_scale() {
+        let known_solutions = KnownSolutions::builtin();
+
+        let sallows = MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS);
+        assert!(known_solutions.find(&sallows).is_some_and(|note| note.starts_with("Lee Sallows")));
+
+        let mut transposed = SALLOWS;
+        for (i, j) in [(1, 3), (2, 6), (5, 7)] { transposed.swap(i, j); }
+        let scaled = MagicSquareCandidate::new(Pattern::Pattern4, 850, transposed.map(|cell| cell * 4));
+        assert!(known_solutions.find(&scaled).is_some());
+
+        let other = MagicSquareCandidate::new(Pattern::Unclassified, 5, [22, 38, 15, 18, 25, 32, 35, 12, 28]);
+        assert!(known_solutions.find(&other).is_none());
+    }
+
+    #[test]
+    fn it_parses_a_catalogue_with_comments_and_notes() {
+        let catalogue = KnownSolutions::parse("# A comment\n\n22,38,15, 18,25,32, 35,12,28 A made-up grid\n").unwrap();
+        let grid = MagicSquareCandidate::new(Pattern::Unclassified, 5, [22, 38, 15, 18, 25, 32, 35, 12, 28]);
+
+        assert_eq!(catalogue.find(&grid), Some("A made-up grid"));
+    }
+
+    #[test]
+    fn it_rejects_invalid_catalogue_lines() {
+        assert!(KnownSolutions::parse("1, 2, 3 Too few cells").is_err());
+        assert!(KnownSolutions::parse("22, 38, 15, 18, 25, 32, 35, 12, 28").is_err());
+        assert!(KnownSolutions::parse("0, 38, 15, 18, 25, 32, 35, 12, 28 A zero cell").is_err());
+    }
+}
@@ -1,33 +1,207 @@
-#![feature(portable_simd)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 
+mod candidate;
+mod checkpoint;
+mod classify;
+mod cli;
 mod composite_number;
+mod known_solutions;
+mod pattern_checker;
 mod patterns_16;
+mod patterns_5;
 mod patterns_234;
 mod pythagorean_triples;
+mod near_misses;
+mod report;
+mod scheduler;
+mod semi_magic;
+mod triple_graph;
+mod triples_table;
+mod verifier;
 
+use candidate::*;
+use checkpoint::*;
+use classify::*;
+use cli::*;
 use composite_number::*;
+use known_solutions::*;
+use pattern_checker::*;
 use patterns_16::*;
+use patterns_5::*;
 use patterns_234::*;
 use pythagorean_triples::*;
+use near_misses::*;
+use report::*;
+use scheduler::*;
+use semi_magic::*;
+use triple_graph::*;
+use triples_table::*;
+use verifier::*;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
 
-const NUM_TRIPLES: usize = 500_000_000;
-const SEARCH_MODE: SearchMode = SearchMode::Patterns16;
-const SEARCH_INTERVAL: u64 = 100_000_000_000;
+// The triples are combined in chunks of this many. From
+// it_benchmarks_the_triple_products, std::simd is fastest with 8 lanes on the
+// baseline x86-64 target and with AVX2, and with 32 on AVX-512. The scalar path
+// is left to the compiler to vectorize, which it does best over 64.
+#[cfg(all(feature = "simd", target_feature = "avx512f"))]
+const SIMD_LANES: usize = 32;
+#[cfg(all(feature = "simd", not(target_feature = "avx512f")))]
+const SIMD_LANES: usize = 8;
+#[cfg(not(feature = "simd"))]
 const SIMD_LANES: usize = 64;
-const PRINT_FACTORS: bool = false;
-const HIDE_KNOWN_SOLUTION: bool = true;
-
-#[allow(dead_code)]
-enum SearchMode { Patterns16, Patterns234 }
 
 fn main() {
-    let pythagorean_triples = PythagoreanTriples::new(NUM_TRIPLES);
-    let max_factors = u64::MAX.ilog(5) as usize;
-
-    CompositeNumber::new(2..=max_factors, 0..SEARCH_INTERVAL, pythagorean_triples).for_each(|primitive_start, a_values, b_values, c| {
-        match SEARCH_MODE {
-            SearchMode::Patterns16 => check_patterns_1_and_6(primitive_start, a_values, b_values, c),
-            SearchMode::Patterns234 => check_patterns_2_3_and_4(a_values, b_values, c),
-        }
-    });
+    let command = Command::parse(std::env::args().skip(1)).unwrap_or_else(|message| exit_with_error(&format!("{message}\n\n{USAGE}")));
+
+    match command {
+        Command::Search(options) => search(options),
+        Command::Triples(options) => triples(options),
+        Command::Check(options) => check(options),
+        Command::Classify(options) => classify(options),
+        Command::Help => println!("{USAGE}"),
+    }
+}
+
+fn search(options: SearchOptions) {
+    let registry = PatternRegistry::for_modes(&options.search_modes, options.min_squares);
+    eprintln!("Checking {}.", registry.names().join(", "));
+    let reporter = Arc::new(open_reporter(options.output.as_deref(), options.format, options.near_misses, options.min_squares, options.show_scaled_copies, options.known_solutions.as_deref()));
+
+    let mut composite_number = match &options.resume {
+        Some(path) => {
+            let checkpoint = Checkpoint::read(path).unwrap_or_else(|error| exit_with_error(&format!("Failed to read the checkpoint: {error}")));
+            eprintln!("Resuming from the checkpoint {} in the range {:?}.", path.display(), checkpoint.search_range);
+
+            checkpoint.check_search(&options.search_modes, options.min_squares).unwrap_or_else(|message| exit_with_error(&message));
+            reporter.restore(&checkpoint);
+
+            let pythagorean_triples = load_triples(options.triples_cache.as_deref(), Some(checkpoint.num_triples), checkpoint.num_triples);
+            CompositeNumber::resume(checkpoint, registry.triple_orders(), options.print_factors, pythagorean_triples).unwrap_or_else(|message| exit_with_error(&message))
+        },
+        None => {
+            let pythagorean_triples = load_triples(options.triples_cache.as_deref(), None, options.num_triples);
+            let max_factors = u64::MAX.ilog(5) as usize;
+
+            CompositeNumber::new(2..=max_factors, options.start_range(), registry.triple_orders(), options.print_factors, pythagorean_triples)
+        },
+    };
+
+    composite_number.skip_centers_below(registry.min_triples());
+
+    if let Some(path) = options.checkpoint.as_ref().or(options.resume.as_ref()) {
+        composite_number.checkpoint_to(path.clone(), options.checkpoint_interval);
+
+        let (search_modes, min_squares, reporter) = (options.search_modes.clone(), options.min_squares, Arc::clone(&reporter));
+        composite_number.save_in_checkpoints(move |checkpoint| {
+            checkpoint.search_modes = search_modes.clone();
+            checkpoint.min_squares = min_squares;
+            reporter.save(checkpoint);
+        });
+    }
+
+    composite_number.for_each(|triples| registry.check(triples, &reporter), |range| reporter.finish_range(range)).unwrap_or_else(|message| exit_with_error(&message));
+}
+
+fn triples(options: TriplesOptions) {
+    let pythagorean_triples = TriplesTable::from(PythagoreanTriples::new(options.num_triples));
+    let largest_prime = pythagorean_triples.c_values().last().unwrap();
+
+    println!("Computed {} primitive Pythagorean triples for the primes 5..={largest_prime}.", pythagorean_triples.len());
+
+    if let Some(path) = &options.triples_cache {
+        pythagorean_triples.write(path).unwrap_or_else(|error| exit_with_error(&format!("Failed to write {}: {error}", path.display())));
+        println!("Wrote the triples to {}.", path.display());
+    }
+}
+
+// Map the triples from the cache file if there is one, using only the first
+// cached_triples of them if given, otherwise compute num_triples of them.
+fn load_triples(cache: Option<&Path>, cached_triples: Option<usize>, num_triples: usize) -> TriplesTable {
+    match cache {
+        Some(path) => {
+            eprintln!("Loading the primitive Pythagorean triples from {}.", path.display());
+            TriplesTable::read(path, cached_triples).unwrap_or_else(|error| exit_with_error(&format!("Failed to load the triples cache: {error}")))
+        },
+        None => PythagoreanTriples::new(num_triples).into(),
+    }
+}
+
+fn check(options: CheckOptions) {
+    let registry = PatternRegistry::for_modes(&options.search_modes, options.min_squares);
+
+    let mut composite_number = match CompositeNumber::for_center(options.center, registry.triple_orders()) {
+        Ok(composite_number) => composite_number,
+        Err(message) => { eprintln!("{message}"); return; },
+    };
+
+    let factors = CompositeNumber::prime_factors(options.center);
+    eprintln!("Checking the center {} = {}.", options.center, factors.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(" x "));
+    let reporter = open_reporter(options.output.as_deref(), options.format, options.near_misses, options.min_squares, options.show_scaled_copies, options.known_solutions.as_deref());
+
+    composite_number.for_each_in_search_range(|triples| registry.check(triples, &reporter));
+    reporter.finish_range(&(options.center..options.center + 1));
+}
+
+fn classify(options: ClassifyOptions) {
+    let classification = classify::classify(options.cells).unwrap_or_else(|message| exit_with_error(&message));
+    let is_magic = classification.is_magic();
+    let Classification { divisor, mut candidate } = classification;
+    candidate.known = KnownSolutions::builtin().find(&candidate).map(String::from);
+
+    if divisor > 1 { println!("Divided the cells by {divisor}² to normalize them."); }
+    print!("{candidate}");
+
+    let non_squares = (0..9).filter(|&i| !candidate.squares[i]).map(|i| CELL_NAMES[i]).collect::<Vec<_>>();
+    println!("{} of the cells are squares. The non-square cells are: {}.", 9 - non_squares.len(), if non_squares.is_empty() { "none".to_string() } else { non_squares.join(", ") });
+
+    if !is_magic {
+        println!("It isn't a magic square with the center {}² so the search can't find it.", candidate.center);
+        return;
+    }
+
+    let Some(search_mode) = checker_for(&candidate) else {
+        println!("It doesn't match a figure 5 pattern, which needs exactly seven squares including the center.");
+        return;
+    };
+
+    let description = match candidate.pattern {
+        Pattern::Unclassified => format!("It has {} squares", 9 - non_squares.len()),
+        pattern => format!("It is pattern {}", pattern.number()),
+    };
+
+    match is_found_by(&candidate, search_mode) {
+        Ok(true) => println!("{description} and the {} checker finds it at the center {}.", search_mode.name(), candidate.center),
+        Ok(false) => println!("{description} but the {} checker doesn't find it at the center {}.", search_mode.name(), candidate.center),
+        Err(message) => println!("{description} but the search doesn't enumerate its center: {message}"),
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+
+fn open_reporter(path: Option<&Path>, format: OutputFormat, near_misses: Option<usize>, min_squares: usize, show_scaled_copies: bool, known_solutions: Option<&Path>) -> Reporter {
+    let known_solutions = match known_solutions {
+        Some(path) => KnownSolutions::read(path).unwrap_or_else(|message| exit_with_error(&format!("Failed to load the known solutions: {message}"))),
+        None => KnownSolutions::builtin(),
+    };
+
+    let reporter = match near_misses {
+        Some(capacity) => Reporter::with_near_misses(open_output(path), format, NearMisses::new(capacity, min_squares)),
+        None => Reporter::new(open_output(path), format),
+    };
+
+    let reporter = reporter.with_known_solutions(known_solutions);
+    if show_scaled_copies { reporter.showing_scaled_copies() } else { reporter }
+}
+
+fn open_output(path: Option<&Path>) -> Box<dyn Write + Send> {
+    match path {
+        Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|error| exit_with_error(&format!("Failed to open {}: {error}", path.display())))),
+        None => Box::new(std::io::stdout()),
+    }
 }
@@ -0,0 +1,122 @@
+use crate::MagicSquareCandidate;
+use std::cmp::Reverse;
+use std::ops::Range;
+use std::sync::Mutex;
+
+// A leaderboard of the closest near misses in a search range. Candidates are
+// ranked by their number of square cells and then by their score, which is
+// how far each non-square cell is from its nearest square as a fraction of the
+// gap between the squares either side of it, summed over those cells. A score
+// of 0.5 per cell is as far from a square as a cell can be.
+//
+// The board is saved in each checkpoint so a search resumed in the middle of a
+// range carries on with that range's board.
+
+pub struct NearMisses {
+    capacity: usize,
+    min_squares: usize,
+    entries: Mutex<Vec<NearMiss>>,
+}
+
+pub struct NearMiss {
+    pub num_squares: usize,
+    pub score: f64,
+    pub candidate: MagicSquareCandidate,
+}
+
+impl NearMisses {
+    pub fn new(capacity: usize, min_squares: usize) -> Self {
+        Self { capacity, min_squares, entries: Mutex::new(vec![]) }
+    }
+
+    pub fn record(&self, candidate: MagicSquareCandidate) {
+        let num_squares = candidate.squares.iter().filter(|&&s| s).count();
+        if num_squares < self.min_squares { return; }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.push(NearMiss { num_squares, score: score(&candidate), candidate });
+        entries.sort_by(|a, b| (Reverse(a.num_squares), a.score).partial_cmp(&(Reverse(b.num_squares), b.score)).unwrap());
+        entries.truncate(self.capacity);
+    }
+
+    pub fn candidates(&self) -> Vec<MagicSquareCandidate> {
+        self.entries.lock().unwrap().iter().map(|entry| entry.candidate.clone()).collect()
+    }
+
+    // The near misses from best to worst, leaving the board empty for the next
+    // search range.
+    pub fn take(&self) -> Vec<NearMiss> {
+        std::mem::take(&mut self.entries.lock().unwrap())
+    }
+}
+
+impl NearMiss {
+    pub fn to_json(&self, range: &Range<u64>, rank: usize) -> String {
+        format!(r#"{{"range":[{},{}],"rank":{rank},"squares":{},"score":{},"candidate":{}}}"#, range.start, range.end, self.num_squares, self.score, self.candidate.to_json())
+    }
+}
+
+pub fn score(candidate: &MagicSquareCandidate) -> f64 {
+    candidate.cells.iter().zip(&candidate.squares).filter(|&(_, &square)| !square).map(|(&cell, _)| distance_to_square(cell)).sum()
+}
+
+// The cells are below 2c² < 2^128 - 2^65 so the next square can't overflow.
+fn distance_to_square(n: u128) -> f64 {
+    let root = n.isqrt();
+    let below = n - root * root;
+    let above = (root + 1) * (root + 1) - n;
+
+    below.min(above) as f64 / (2 * root + 1) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Pattern, SALLOWS};
+
+    fn candidate(cells: [u128; 9]) -> MagicSquareCandidate {
+        MagicSquareCandidate::new(Pattern::Pattern4, 425, cells)
+    }
+
+    #[test]
+    fn it_scores_cells_by_their_distance_to_the_nearest_square() {
+        assert_eq!(distance_to_square(25), 0.0);
+        assert_eq!(distance_to_square(26), 1.0 / 11.0);
+        assert_eq!(distance_to_square(35), 1.0 / 11.0);
+        assert_eq!(distance_to_square(30), 5.0 / 11.0);
+
+        // 360721 is 480 below 601² and 222121 is 280 above 471².
+        assert_eq!(score(&candidate(SALLOWS)), 480.0 / 1201.0 + 280.0 / 943.0);
+    }
+
+    #[test]
+    fn it_keeps_the_best_near_misses() {
+        let near_misses = NearMisses::new(2, 7);
+
+        let mut fewer_squares = candidate(SALLOWS);
+        fewer_squares.squares[0] = false;
+
+        let mut other_center = candidate(SALLOWS);
+        other_center.center = 850;
+
+        near_misses.record(fewer_squares);
+        near_misses.record(candidate(SALLOWS));
+        near_misses.record(other_center.clone());
+        near_misses.record(MagicSquareCandidate { center: 1275, ..other_center });
+
+        let entries = near_misses.take();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.num_squares == 7 && entry.candidate.cells == SALLOWS));
+        assert_eq!(entries.iter().map(|entry| entry.candidate.center).collect::<Vec<_>>(), &[425, 850]);
+
+        assert!(near_misses.take().is_empty());
+    }
+
+    #[test]
+    fn it_can_format_a_near_miss_as_a_json_line() {
+        let near_miss = NearMiss { num_squares: 7, score: 0.25, candidate: candidate(SALLOWS) };
+        let json = near_miss.to_json(&(0..1000), 1);
+
+        assert!(json.starts_with(r#"{"range":[0,1000],"rank":1,"squares":7,"score":0.25,"candidate":{"pattern":4,"center":425,"#));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{MagicSquareCandidate, Reporter, SearchMode, Patterns16Checker, Patterns234Checker, Patterns5Checker, SemiMagicChecker, TripleGraphChecker};
+use rayon::prelude::*;
+
+// Each pattern checker declares how it needs the magic triples of a center to
+// be sorted. The enumeration sorts them once per order that is in use and the
+// registry hands each ordering to the checkers that asked for it, so any set
+// of checkers can run in a single pass over the centers.
+//
+// Each checker also declares the fewest magic triples a center needs for its
+// patterns, i.e. the number of lines through the center that are square. The
+// enumeration skips centers with fewer triples before building any of them.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TripleOrder {
+    // The non-primitive triples sorted by a, followed by the primitive ones.
+    ByPrimitiveAndA,
+    // All triples sorted by a.
+    ByA,
+}
+
+// The enumeration runs many centers in parallel, but a center with many
+// factors has thousands of magic triples and its O(n²) loops would keep one
+// thread busy long after the centers around it have finished. Above this
+// many triples the checkers split their outer loop into parallel tasks.
+//
+// A thread waiting for those tasks can pick up another center in the meantime
+// so thread-local buffers are taken for the length of a check rather than
+// borrowed, otherwise the other center would find them already borrowed.
+pub const PARALLEL_MIN_TRIPLES: usize = 512;
+
+pub fn for_each_outer_index(len: usize, parallel: bool, f: impl Fn(usize) + Send + Sync) {
+    if parallel { (0..len).into_par_iter().for_each(f); } else { (0..len).for_each(f); }
+}
+
+pub struct MagicTriples<'a> {
+    pub order: TripleOrder,
+    pub primitive_start: usize,
+    pub a_values: &'a [u64],
+    pub b_values: &'a [u64],
+    pub center: u64,
+    pub factors: &'a [u64],
+}
+
+pub trait PatternChecker: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn triple_order(&self) -> TripleOrder;
+    fn min_triples(&self) -> usize;
+    fn check(&self, triples: &MagicTriples, report: &(dyn Fn(MagicSquareCandidate) + Sync));
+}
+
+pub struct PatternRegistry {
+    checkers: Vec<Box<dyn PatternChecker>>,
+}
+
+impl PatternRegistry {
+    pub fn new(checkers: Vec<Box<dyn PatternChecker>>) -> Self {
+        Self { checkers }
+    }
+
+    pub fn for_modes(search_modes: &[SearchMode], min_squares: usize) -> Self {
+        Self::new(search_modes.iter().map(|mode| -> Box<dyn PatternChecker> {
+            match mode {
+                SearchMode::Patterns16 => Box::new(Patterns16Checker),
+                SearchMode::Patterns234 => Box::new(Patterns234Checker),
+                SearchMode::Patterns5 => Box::new(Patterns5Checker),
+                SearchMode::Graph => Box::new(TripleGraphChecker { min_squares }),
+                SearchMode::SemiMagic => Box::new(SemiMagicChecker),
+            }
+        }).collect())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.checkers.iter().map(|checker| checker.name()).collect()
+    }
+
+    // Sorting by a can follow sorting by primitive and a, but not the other way
+    // round, so the orders are returned in that sequence.
+    pub fn triple_orders(&self) -> Vec<TripleOrder> {
+        let mut orders = self.checkers.iter().map(|checker| checker.triple_order()).collect::<Vec<_>>();
+        orders.sort();
+        orders.dedup();
+        orders
+    }
+
+    // A center is only skipped if none of the checkers could use it.
+    pub fn min_triples(&self) -> usize {
+        self.checkers.iter().map(|checker| checker.min_triples()).min().unwrap_or(0)
+    }
+
+    pub fn check(&self, triples: &MagicTriples, reporter: &Reporter) {
+        self.check_each(triples, &|candidate| reporter.report(candidate));
+    }
+
+    // Run the checkers that asked for the order of these triples and pass
+    // their candidates to report with the factors of the center filled in.
+    pub fn check_each(&self, triples: &MagicTriples, report: &(dyn Fn(MagicSquareCandidate) + Sync)) {
+        let report = |mut candidate: MagicSquareCandidate| {
+            candidate.factors = triples.factors.to_vec();
+            report(candidate);
+        };
+
+        for checker in self.checkers.iter().filter(|checker| checker.triple_order() == triples.order) {
+            checker.check(triples, &report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CompositeNumber, OutputFormat, PythagoreanTriples, SharedBuffer};
+    use std::sync::Mutex;
+
+    fn candidates(search_modes: &[SearchMode], center_range: std::ops::Range<u64>) -> Vec<String> {
+        let registry = PatternRegistry::for_modes(search_modes, 7);
+        let buffer = SharedBuffer::default();
+        let reporter = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines);
+
+        let mut composite_number = CompositeNumber::new(2..=4, center_range.clone(), registry.triple_orders(), false, PythagoreanTriples::new(200));
+        composite_number.for_each_in_search_range(|triples| registry.check(triples, &reporter));
+        reporter.finish_range(&center_range);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let mut lines = output.lines().map(String::from).collect::<Vec<_>>();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn it_returns_each_triple_order_once_in_a_compatible_sequence() {
+        let registry = PatternRegistry::for_modes(&[SearchMode::Patterns234, SearchMode::Patterns16, SearchMode::Patterns234], 7);
+
+        assert_eq!(registry.names(), &["patterns234", "patterns16", "patterns234"]);
+        assert_eq!(registry.triple_orders(), &[TripleOrder::ByPrimitiveAndA, TripleOrder::ByA]);
+    }
+
+    #[test]
+    fn it_only_skips_centers_that_none_of_the_checkers_could_use() {
+        assert_eq!(PatternRegistry::for_modes(&[SearchMode::Patterns16], 7).min_triples(), 3);
+        assert_eq!(PatternRegistry::for_modes(&[SearchMode::Patterns16, SearchMode::Patterns234], 7).min_triples(), 2);
+        assert_eq!(PatternRegistry::for_modes(&[SearchMode::Graph], 9).min_triples(), 4);
+        assert_eq!(PatternRegistry::new(vec![]).min_triples(), 0);
+    }
+
+    // The table only has the first 100 Pythagorean primes so this reaches the
+    // centers in the range whose non-final factors are among them. The largest
+    // cells depend on the size of the center rather than its factors.
+    #[test]
+    fn it_checks_centers_with_small_factors_just_below_the_largest_supported_one_without_overflowing() {
+        let registry = PatternRegistry::for_modes(&[SearchMode::Patterns16, SearchMode::Patterns234, SearchMode::Patterns5, SearchMode::Graph], 7);
+        let num_checked = Mutex::new(0);
+
+        let mut composite_number = CompositeNumber::new(2..=3, crate::MAX_CENTER - 20_000..crate::MAX_CENTER + 1, registry.triple_orders(), false, PythagoreanTriples::new(100));
+        composite_number.for_each_in_search_range(|triples| {
+            registry.check_each(triples, &|candidate| assert!(candidate.cells.iter().all(|&cell| cell < 2 * candidate.center as u128 * candidate.center as u128)));
+            *num_checked.lock().unwrap() += 1;
+        });
+
+        assert!(num_checked.into_inner().unwrap() > 0);
+    }
+
+    #[test]
+    fn it_runs_several_checkers_in_one_pass() {
+        let both = candidates(&[SearchMode::Patterns16, SearchMode::Patterns234], 0..30_000);
+
+        let mut separately = candidates(&[SearchMode::Patterns16], 0..30_000);
+        separately.extend(candidates(&[SearchMode::Patterns234], 0..30_000));
+        separately.sort();
+        separately.dedup();
+
+        assert!(both.iter().any(|line| line.contains(r#""center":425,"#)));
+        assert_eq!(both, separately);
+    }
+}
@@ -1,119 +1,361 @@
 use std::cell::RefCell;
-use crate::patterns_234::print;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasherDefault, Hasher};
+use crate::{MagicSquareCandidate, Pattern, PatternChecker, MagicTriples, TripleOrder, PARALLEL_MIN_TRIPLES, for_each_outer_index};
 
 // The patterns are from figure 5 of http://www.multimagie.com/Search.pdf#page=2
 
-pub fn check_patterns_1_and_6(primitive_start: usize, a_values: &[u64], b_values: &[u64], c: u64) {
-    let center = c as u128;
-    let squared_center = center * center;
-    let magic_sum = squared_center * 3;
+type SquaredTriples = Vec<(u128, u128)>;
+
+pub struct Patterns16Checker;
+
+impl PatternChecker for Patterns16Checker {
+    fn name(&self) -> &'static str { "patterns16" }
+    fn triple_order(&self) -> TripleOrder { TripleOrder::ByPrimitiveAndA }
+
+    // Both non-square cells are on the same line so the other three are square.
+    fn min_triples(&self) -> usize { 3 }
 
+    fn check(&self, triples: &MagicTriples, report: &(dyn Fn(MagicSquareCandidate) + Sync)) {
+        check_patterns_1_and_6(triples.primitive_start, triples.a_values, triples.b_values, triples.center, &report);
+    }
+}
+
+pub fn check_patterns_1_and_6(primitive_start: usize, a_values: &[u64], b_values: &[u64], c: u64, report: &(impl Fn(MagicSquareCandidate) + Sync)) {
     thread_local! {
-        static TRIPLES: RefCell<(Vec<(u128, u128)>, Vec<(u128, u128)>)> = const { RefCell::new((vec![], vec![])) };
+        static TRIPLES: RefCell<(SquaredTriples, SquaredTriples, HashedLookup)> = RefCell::new((vec![], vec![], HashedLookup::default()));
     }
 
-    TRIPLES.with_borrow_mut(|(non_primitive, primitive)| {
-        non_primitive.clear();
-        non_primitive.extend(a_values[..primitive_start].iter().zip(b_values[..primitive_start].iter()).map(|(&a, &b)| { let a = a as u128; let b = b as u128; (a * a, b * b) }));
+    let (mut non_primitive, mut primitive, mut lookup) = TRIPLES.take();
+
+    non_primitive.clear();
+    non_primitive.extend(a_values[..primitive_start].iter().zip(b_values[..primitive_start].iter()).map(|(&a, &b)| { let a = a as u128; let b = b as u128; (a * a, b * b) }));
+
+    primitive.clear();
+    primitive.extend(a_values[primitive_start..].iter().zip(b_values[primitive_start..].iter()).map(|(&a, &b)| { let a = a as u128; let b = b as u128; (a * a, b * b) }));
+
+    lookup.rebuild(&non_primitive, &primitive);
+    check_with_lookup(&non_primitive, &primitive, c, &lookup, a_values.len() >= PARALLEL_MIN_TRIPLES, report);
 
-        primitive.clear();
-        primitive.extend(a_values[primitive_start..].iter().zip(b_values[primitive_start..].iter()).map(|(&a, &b)| { let a = a as u128; let b = b as u128; (a * a, b * b) }));
+    TRIPLES.set((non_primitive, primitive, lookup));
+}
+
+// For tests that build the squared triples themselves.
+#[cfg(test)]
+pub fn check_squared_triples(non_primitive: &[(u128, u128)], primitive: &[(u128, u128)], c: u64, report: &(impl Fn(MagicSquareCandidate) + Sync)) {
+    let mut lookup = HashedLookup::default();
+    lookup.rebuild(non_primitive, primitive);
+    check_with_lookup(non_primitive, primitive, c, &lookup, false, report);
+}
 
-        for (i, &(top_left, bottom_right)) in primitive.iter().enumerate() {
-            let remainder1 = magic_sum - top_left;
-            let remainder2 = magic_sum - bottom_right;
+// Each target is a pair of cells on a line through the center, so it sums to
+// 2c² and is a magic triple exactly when its larger square is the a² of one.
+// The primitive triples can only be paired with those before the current one.
+trait TripleLookup: Sync {
+    fn has_primitive_before(&self, index: usize, target: (u128, u128)) -> bool;
+    fn has_non_primitive(&self, target: (u128, u128)) -> bool;
+}
 
-            let upto_index1 = primitive[..i].partition_point(|&(square, _)| square < remainder1);
-            let upto_index2 = non_primitive.partition_point(|&(square, _)| square < remainder1);
+// The triples sorted by a, searched with a binary search for every target.
+// This was the original lookup and is kept to check the hashed one against.
+#[cfg(test)]
+struct SortedLookup<'a> {
+    non_primitive: &'a [(u128, u128)],
+    primitive: &'a [(u128, u128)],
+}
 
-            for &(middle_left, middle_right) in &primitive[..upto_index1] {
-                let bottom_left = remainder1 - middle_left; // smaller
-                let top_right = remainder2 - middle_right; // bigger (increasing)
-                let pattern_1_target = (top_right, bottom_left);
+#[cfg(test)]
+impl TripleLookup for SortedLookup<'_> {
+    fn has_primitive_before(&self, index: usize, target: (u128, u128)) -> bool {
+        self.primitive[..index].binary_search(&target).is_ok()
+    }
 
-                let bottom_middle = remainder2 - bottom_left; // bigger
-                let Some(top_middle) = remainder1.checked_sub(top_right) else { break }; // smaller
-                let pattern_6_target = (bottom_middle, top_middle);
+    fn has_non_primitive(&self, target: (u128, u128)) -> bool {
+        self.non_primitive.binary_search(&target).is_ok()
+    }
+}
 
-                if primitive[..i].binary_search(&pattern_1_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right);
-                };
+// The a² of each triple in a hash table built once per center, mapped to its
+// index for the primitive triples. It is kept between centers to reuse its
+// allocations.
+#[derive(Default)]
+struct HashedLookup {
+    non_primitive: HashSet<u128, BuildHasherDefault<SquareHasher>>,
+    primitive: HashMap<u128, usize, BuildHasherDefault<SquareHasher>>,
+}
 
-                if non_primitive.binary_search(&pattern_1_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right);
-                }
+impl HashedLookup {
+    fn rebuild(&mut self, non_primitive: &[(u128, u128)], primitive: &[(u128, u128)]) {
+        self.non_primitive.clear();
+        self.non_primitive.extend(non_primitive.iter().map(|&(a_square, _)| a_square));
 
-                if primitive[..i].binary_search(&pattern_6_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right);
-                };
+        self.primitive.clear();
+        self.primitive.extend(primitive.iter().enumerate().map(|(i, &(a_square, _))| (a_square, i)));
+    }
+}
 
-                if non_primitive.binary_search(&pattern_6_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right);
-                }
-            }
+impl TripleLookup for HashedLookup {
+    fn has_primitive_before(&self, index: usize, (a_square, _): (u128, u128)) -> bool {
+        self.primitive.get(&a_square).is_some_and(|&i| i < index)
+    }
+
+    fn has_non_primitive(&self, (a_square, _): (u128, u128)) -> bool {
+        self.non_primitive.contains(&a_square)
+    }
+}
+
+// The keys are squares of distinct numbers so a multiplicative hash of their
+// two halves spreads them well enough, and is much cheaper than SipHash.
+#[derive(Default)]
+struct SquareHasher(u64);
+
+impl Hasher for SquareHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u64(&mut self, word: u64) {
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+
+    fn write_u128(&mut self, word: u128) {
+        self.write_u64(word as u64);
+        self.write_u64((word >> 64) as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn check_with_lookup(non_primitive: &[(u128, u128)], primitive: &[(u128, u128)], c: u64, lookup: &impl TripleLookup, parallel: bool, report: &(impl Fn(MagicSquareCandidate) + Sync)) {
+    let center = c as u128;
+    let squared_center = center * center;
+    let magic_sum = squared_center * 3;
+
+    for_each_outer_index(primitive.len(), parallel, |i| {
+        let (top_left, bottom_right) = primitive[i];
+
+        let remainder1 = magic_sum - top_left;
+        let remainder2 = magic_sum - bottom_right;
+
+        let upto_index1 = primitive[..i].partition_point(|&(square, _)| square < remainder1);
+        let upto_index2 = non_primitive.partition_point(|&(square, _)| square < remainder1);
+
+        for &(middle_left, middle_right) in &primitive[..upto_index1] {
+            let bottom_left = remainder1 - middle_left; // smaller
+            let top_right = remainder2 - middle_right; // bigger (increasing)
+            let pattern_1_target = (top_right, bottom_left);
+
+            let bottom_middle = remainder2 - bottom_left; // bigger
+            let Some(top_middle) = remainder1.checked_sub(top_right) else { break }; // smaller
 
-            for &(middle_left, middle_right) in &non_primitive[..upto_index2] {
-                let bottom_left = remainder1 - middle_left; // smaller
-                let top_right = remainder2 - middle_right; // bigger (increasing)
-                let pattern_1_target = (top_right, bottom_left);
+            if lookup.has_primitive_before(i, pattern_1_target) {
+                report(MagicSquareCandidate::new(Pattern::Pattern1, c, [top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right]));
+            };
 
-                let bottom_middle = remainder2 - bottom_left; // bigger
-                let Some(top_middle) = remainder1.checked_sub(top_right) else { break }; // smaller
-                let pattern_6_target = (bottom_middle, top_middle);
+            if lookup.has_non_primitive(pattern_1_target) {
+                report(MagicSquareCandidate::new(Pattern::Pattern1, c, [top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right]));
+            }
+        }
+
+        for &(middle_left, middle_right) in &non_primitive[..upto_index2] {
+            let bottom_left = remainder1 - middle_left; // smaller
+            let top_right = remainder2 - middle_right; // bigger (increasing)
+            let pattern_1_target = (top_right, bottom_left);
 
-                if non_primitive.binary_search(&pattern_1_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right);
-                }
+            let bottom_middle = remainder2 - bottom_left; // bigger
+            let Some(top_middle) = remainder1.checked_sub(top_right) else { break }; // smaller
 
-                if non_primitive.binary_search(&pattern_6_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right);
-                }
+            if lookup.has_non_primitive(pattern_1_target) {
+                report(MagicSquareCandidate::new(Pattern::Pattern1, c, [top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right]));
             }
+        }
+
+        // Check the symmetrical case where (middle_left, middle_right) are swapped.
+        for &(middle_right, middle_left) in &primitive[..i] {
+            let bottom_left = remainder1 - middle_left; // bigger or smaller
+            let top_right = remainder2 - middle_right; // bigger or smaller (decreasing)
+            let pattern_1_target = if top_right > bottom_left { (top_right, bottom_left) } else { (bottom_left, top_right) };
 
-            // Check the symmetrical case where (middle_left, middle_right) are swapped.
-            for &(middle_right, middle_left) in &primitive[..i] {
-                let bottom_left = remainder1 - middle_left; // bigger or smaller
-                let top_right = remainder2 - middle_right; // bigger or smaller (decreasing)
-                let pattern_1_target = if top_right > bottom_left { (top_right, bottom_left) } else { (bottom_left, top_right) };
+            let bottom_middle = remainder2 - bottom_left; // bigger or smaller
+            let Some(top_middle) = remainder1.checked_sub(top_right) else { continue }; // bigger or smaller
 
-                let bottom_middle = remainder2 - bottom_left; // bigger or smaller
-                let Some(top_middle) = remainder1.checked_sub(top_right) else { continue }; // bigger or smaller
-                let pattern_6_target = if bottom_middle > top_middle { (bottom_middle, top_middle) } else { (top_middle, bottom_middle) };
+            if lookup.has_primitive_before(i, pattern_1_target) {
+                report(MagicSquareCandidate::new(Pattern::Pattern1, c, [top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right]));
+            };
 
-                if primitive[..i].binary_search(&pattern_1_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_right, squared_center, middle_left, bottom_left, bottom_middle, bottom_right);
-                };
+            if lookup.has_non_primitive(pattern_1_target) {
+                report(MagicSquareCandidate::new(Pattern::Pattern1, c, [top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right]));
+            }
+        }
 
-                if non_primitive.binary_search(&pattern_1_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_right, squared_center, middle_left, bottom_left, bottom_middle, bottom_right);
-                }
+        for &(middle_right, middle_left) in non_primitive.iter() {
+            let bottom_left = remainder1 - middle_left; // bigger or smaller
+            let top_right = remainder2 - middle_right; // bigger or smaller (decreasing)
+            let pattern_1_target = if top_right > bottom_left { (top_right, bottom_left) } else { (bottom_left, top_right) };
 
-                if primitive[..i].binary_search(&pattern_6_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_right, squared_center, middle_left, bottom_left, bottom_middle, bottom_right);
-                };
+            let bottom_middle = remainder2 - bottom_left; // bigger or smaller
+            let Some(top_middle) = remainder1.checked_sub(top_right) else { continue }; // bigger or smaller
 
-                if non_primitive.binary_search(&pattern_6_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_right, squared_center, middle_left, bottom_left, bottom_middle, bottom_right);
-                }
+            if lookup.has_non_primitive(pattern_1_target) {
+                report(MagicSquareCandidate::new(Pattern::Pattern1, c, [top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right]));
             }
+        }
+    });
+
+    check_pattern_6(non_primitive, primitive, c, parallel, report);
+}
+
+thread_local! {
+    static SQUARES: RefCell<Vec<u128>> = const { RefCell::new(vec![]) };
+}
+
+// Pattern 6 has no second diagonal to pair the triples by, so every triple goes
+// on the diagonal with its larger square in the top left. The middle left and
+// top middle then have to sum to 4c² - 2 * top left, and the pairs of squares
+// that do are found by walking the sorted squares of all triples from both
+// ends. Reflecting in the diagonal swaps the middle row and column so only
+// report the squares whose middle column has the larger a.
+fn check_pattern_6(non_primitive: &[(u128, u128)], primitive: &[(u128, u128)], c: u64, parallel: bool, report: &(impl Fn(MagicSquareCandidate) + Sync)) {
+    let center = c as u128;
+    let squared_center = center * center;
+    let center_sum = squared_center * 2;
+    let magic_sum = squared_center * 3;
+
+    let mut squares = SQUARES.take();
+    squares.clear();
+    squares.extend(non_primitive.iter().chain(primitive).flat_map(|&(a_square, b_square)| [a_square, b_square]));
+    squares.sort_unstable();
 
-            for &(middle_right, middle_left) in non_primitive.iter() {
-                let bottom_left = remainder1 - middle_left; // bigger or smaller
-                let top_right = remainder2 - middle_right; // bigger or smaller (decreasing)
-                let pattern_1_target = if top_right > bottom_left { (top_right, bottom_left) } else { (bottom_left, top_right) };
+    let triple_of = |square: u128| if square > squared_center { (square, center_sum - square) } else { (center_sum - square, square) };
+    let num_triples = non_primitive.len() + primitive.len();
 
-                let bottom_middle = remainder2 - bottom_left; // bigger or smaller
-                let Some(top_middle) = remainder1.checked_sub(top_right) else { continue }; // bigger or smaller
-                let pattern_6_target = if bottom_middle > top_middle { (bottom_middle, top_middle) } else { (top_middle, bottom_middle) };
+    for_each_outer_index(num_triples, parallel, |i| {
+        let diagonal = if i < non_primitive.len() { non_primitive[i] } else { primitive[i - non_primitive.len()] };
+        let (top_left, bottom_right) = diagonal;
+        let target = 2 * (center_sum - top_left);
 
-                if non_primitive.binary_search(&pattern_1_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_right, squared_center, middle_left, bottom_left, bottom_middle, bottom_right);
-                }
+        let (mut low, mut high) = (0, squares.partition_point(|&square| square < target));
 
-                if non_primitive.binary_search(&pattern_6_target).is_ok() {
-                    print(top_left, top_middle, top_right, middle_right, squared_center, middle_left, bottom_left, bottom_middle, bottom_right);
-                }
+        while low + 1 < high {
+            let (smaller, bigger) = (squares[low], squares[high - 1]);
+
+            match (smaller + bigger).cmp(&target) {
+                Ordering::Less => { low += 1; continue; }
+                Ordering::Greater => { high -= 1; continue; }
+                Ordering::Equal => { low += 1; high -= 1; }
+            }
+
+            for (middle_left, top_middle) in [(smaller, bigger), (bigger, smaller)] {
+                let (row, column) = (triple_of(middle_left), triple_of(top_middle));
+                if row == diagonal || column == diagonal || column.0 <= row.0 { continue; }
+
+                let middle_right = center_sum - middle_left;
+                let bottom_middle = center_sum - top_middle;
+                let Some(top_right) = magic_sum.checked_sub(bottom_right + middle_right).filter(|&cell| cell > 0) else { continue };
+                let Some(bottom_left) = center_sum.checked_sub(top_right).filter(|&cell| cell > 0) else { continue };
+
+                report(MagicSquareCandidate::new(Pattern::Pattern6, c, [top_left, top_middle, top_right, middle_left, squared_center, middle_right, bottom_left, bottom_middle, bottom_right]));
             }
         }
     });
+
+    SQUARES.set(squares);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CompositeNumber, TripleOrder, gcd};
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    // Centers with several Pythagorean prime factors and so many magic triples.
+    const CENTERS: [u64; 4] = [5 * 5 * 13 * 17 * 29 * 37, 5 * 13 * 17 * 29 * 37 * 41, 5 * 5 * 13 * 13 * 17 * 29 * 37, 5 * 13 * 17 * 29 * 37 * 41 * 53];
+
+    // The squared magic triples of the center split into non-primitive and
+    // primitive ones, i.e. those whose roots share a factor with the center.
+    fn squared_triples(center: u64) -> (SquaredTriples, SquaredTriples) {
+        let mut composite_number = CompositeNumber::for_center(center, vec![TripleOrder::ByA]).unwrap();
+        let triples = Mutex::new(vec![]);
+
+        composite_number.for_each_in_search_range(|t| {
+            triples.lock().unwrap().extend(t.a_values.iter().zip(t.b_values).map(|(&a, &b)| (gcd(a as u128, center as u128) == 1, (a as u128 * a as u128, b as u128 * b as u128))));
+        });
+
+        let (primitive, non_primitive): (Vec<_>, Vec<_>) = triples.into_inner().unwrap().into_iter().partition(|&(is_primitive, _)| is_primitive);
+        (non_primitive.into_iter().map(|(_, t)| t).collect(), primitive.into_iter().map(|(_, t)| t).collect())
+    }
+
+    fn hits(non_primitive: &[(u128, u128)], primitive: &[(u128, u128)], c: u64, lookup: &impl TripleLookup, parallel: bool) -> Vec<[u128; 9]> {
+        let hits = Mutex::new(vec![]);
+        check_with_lookup(non_primitive, primitive, c, lookup, parallel, &|candidate| hits.lock().unwrap().push(candidate.cells));
+
+        let mut hits = hits.into_inner().unwrap();
+        if parallel { hits.sort(); }
+        hits
+    }
+
+    fn time(repetitions: u32, f: impl Fn()) -> Duration {
+        let start = Instant::now();
+        for _ in 0..repetitions { f(); }
+        start.elapsed() / repetitions
+    }
+
+    // Stand-ins for magic triples: pairs (c² + d, c² - d) that sum to 2c², with
+    // the even differences treated as non-primitive. They contain many more
+    // pattern 1 and 6 arrangements than real centers do.
+    #[test]
+    fn it_finds_the_same_squares_with_either_lookup() {
+        let c = 1000;
+        let center_square = c as u128 * c as u128;
+        let triples = (1..=200).map(|d| d * 3).map(|d| (center_square + d, center_square - d));
+        let (non_primitive, primitive): (Vec<_>, Vec<_>) = triples.partition(|&(a_square, _)| (a_square - center_square).is_multiple_of(2));
+
+        let mut hashed = HashedLookup::default();
+        hashed.rebuild(&non_primitive, &primitive);
+
+        let expected = hits(&non_primitive, &primitive, c, &SortedLookup { non_primitive: &non_primitive, primitive: &primitive }, false);
+        let actual = hits(&non_primitive, &primitive, c, &hashed, false);
+
+        assert!(!expected.is_empty());
+        assert_eq!(actual, expected);
+
+        let mut sorted = expected;
+        sorted.sort();
+        assert_eq!(hits(&non_primitive, &primitive, c, &hashed, true), sorted);
+    }
+
+    // cargo test --release -- --ignored --nocapture it_benchmarks
+    //
+    // The hashed lookup took 70-98% of the time of the binary search on these
+    // centers, e.g. 9.16ms against 12.0ms for the 1,093 triples of the last.
+    // Both times include the pattern 6 walk, which doesn't use the lookup.
+    #[test]
+    #[ignore]
+    fn it_benchmarks_the_hashed_lookup_against_binary_search() {
+        for c in CENTERS {
+            let (non_primitive, primitive) = squared_triples(c);
+            let sorted = SortedLookup { non_primitive: &non_primitive, primitive: &primitive };
+            let mut hashed = HashedLookup::default();
+
+            hashed.rebuild(&non_primitive, &primitive);
+            assert_eq!(hits(&non_primitive, &primitive, c, &hashed, false), hits(&non_primitive, &primitive, c, &sorted, false));
+
+            let sorted_time = time(100, || { check_with_lookup(&non_primitive, &primitive, c, &sorted, false, &|_| {}); });
+            let hashed_time = time(100, || {
+                let mut hashed = HashedLookup::default();
+                hashed.rebuild(&non_primitive, &primitive);
+                check_with_lookup(&non_primitive, &primitive, c, &hashed, false, &|_| {});
+            });
+
+            println!("center {c} with {} non-primitive and {} primitive triples: binary search {sorted_time:?}, hashed {hashed_time:?}", non_primitive.len(), primitive.len());
+        }
+    }
 }
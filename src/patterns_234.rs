@@ -1,117 +1,212 @@
+use crate::{MagicSquareCandidate, Pattern, PatternChecker, MagicTriples, TripleOrder, PARALLEL_MIN_TRIPLES, for_each_outer_index};
 use std::cell::RefCell;
 
 // The patterns are from figure 5 of http://www.multimagie.com/Search.pdf#page=2
 
+pub struct Patterns234Checker;
+
+impl PatternChecker for Patterns234Checker {
+    fn name(&self) -> &'static str { "patterns234" }
+    fn triple_order(&self) -> TripleOrder { TripleOrder::ByA }
+
+    // Only the diagonals are square in pattern 2, and a diagonal and the middle
+    // row in patterns 3 and 4.
+    fn min_triples(&self) -> usize { 2 }
+
+    fn check(&self, triples: &MagicTriples, report: &(dyn Fn(MagicSquareCandidate) + Sync)) {
+        check_patterns_2_3_and_4(triples.a_values, triples.b_values, triples.center, &report);
+    }
+}
+
 thread_local! {
     static SQUARES: RefCell<(Vec<u128>, Vec<u128>)> = const { RefCell::new((vec![], vec![])) };
 }
 
-pub fn check_patterns_2_3_and_4(a_values: &[u64], b_values: &[u64], c: u64) {
-    if crate::HIDE_KNOWN_SOLUTION && c % 425 == 0 { return; }
+pub fn check_patterns_2_3_and_4(a_values: &[u64], b_values: &[u64], c: u64, report: &(impl Fn(MagicSquareCandidate) + Sync)) {
+    let (mut a_squares, mut b_squares) = SQUARES.take();
+
+    a_squares.clear();
+    a_squares.extend(a_values.iter().map(|&a| { let a = a as u128; a * a }));
 
+    b_squares.clear();
+    b_squares.extend(b_values.iter().map(|&b| { let b = b as u128; b * b }));
+
+    check_squares(&a_squares, &b_squares, c, is_square, a_values.len() >= PARALLEL_MIN_TRIPLES, report);
+    SQUARES.set((a_squares, b_squares));
+}
+
+// The is_square for the candidates in the inner loops is passed in so the tests
+// can compare the residue prefilter with the root on real centers.
+fn check_squares(a_squares: &[u128], b_squares: &[u128], c: u64, is_square: impl Fn(u128) -> bool + Sync, parallel: bool, report: &(impl Fn(MagicSquareCandidate) + Sync)) {
     let center = c as u128;
     let center_square = center * center;
     let center_sum = center_square + center_square;
     let magic_sum = center_sum + center_square;
 
-    SQUARES.with_borrow_mut(|(a_squares, b_squares)| {
-        a_squares.clear();
-        a_squares.extend(a_values.iter().map(|&a| { let a = a as u128; a * a }));
+    for_each_outer_index(a_squares.len(), parallel, |i| {
+        let (a_square1, b_square1) = (a_squares[i], b_squares[i]);
+        let other_a_squares = &a_squares[i + 1..];
+        let other_b_squares = &b_squares[i + 1..];
 
-        b_squares.clear();
-        b_squares.extend(b_values.iter().map(|&b| { let b = b as u128; b * b }));
+        let a_remainder = magic_sum - a_square1;
+        let b_remainder = magic_sum - b_square1;
+        let b_minimum = a_square1 - center_square;
 
-        for (i, (&a_square1, &b_square1)) in a_squares.iter().zip(b_squares.iter()).enumerate() {
-            let other_a_squares = &a_squares[i + 1..];
-            let other_b_squares = &b_squares[i + 1..];
+        let a_upto = other_a_squares.partition_point(|&s| s < a_remainder);
+        let b_upto = other_b_squares.partition_point(|&s| s >= b_minimum);
 
-            let a_remainder = magic_sum - a_square1;
-            let b_remainder = magic_sum - b_square1;
-            let b_minimum = a_square1 - center_square;
-
-            let a_upto = other_a_squares.partition_point(|&s| s < a_remainder);
-            let b_upto = other_b_squares.partition_point(|&s| s >= b_minimum);
-
-            for (&a_square2, &b_square2) in other_a_squares[..a_upto].iter().zip(&other_b_squares[..a_upto]) {
-                let aa_candidate = a_remainder - a_square2;
-                if is_square(aa_candidate) {
-                    check_pattern_2(aa_candidate, a_square1, b_square1, a_square2, b_square2, center_square);
-                    check_pattern_3_and_4(aa_candidate, a_square1, a_square2, b_square1, b_square2, center_square, magic_sum, center_sum);
-                }
+        for (&a_square2, &b_square2) in other_a_squares[..a_upto].iter().zip(&other_b_squares[..a_upto]) {
+            let aa_candidate = a_remainder - a_square2;
+            if is_square(aa_candidate) {
+                check_pattern_2(a_square1, a_square2, center_square, c, report);
+                check_pattern_3_and_4(aa_candidate, b_square1, b_square2, center_square, c, report);
             }
+        }
 
-            for (&a_square2, &b_square2) in other_a_squares.iter().zip(other_b_squares) {
-                let ab_candidate = a_remainder - b_square2;
-                if is_square(ab_candidate) {
-                    check_pattern_3_and_4(ab_candidate, a_square1, b_square2, b_square1, a_square2, center_square, magic_sum, center_sum);
-                }
-
-                let ba_candidate = b_remainder - a_square2;
-                if is_square(ba_candidate) {
-                    check_pattern_3_and_4(ba_candidate, b_square1, a_square2, a_square1, b_square2, center_square, magic_sum, center_sum);
-                }
+        for (&a_square2, &b_square2) in other_a_squares.iter().zip(other_b_squares) {
+            let ab_candidate = a_remainder - b_square2;
+            if is_square(ab_candidate) {
+                check_pattern_3_and_4(ab_candidate, b_square1, a_square2, center_square, c, report);
             }
 
-            for (&a_square2, &b_square2) in other_a_squares[..b_upto].iter().zip(&other_b_squares[..b_upto]) {
-                let bb_candidate = b_remainder - b_square2;
-                if is_square(bb_candidate) {
-                    check_pattern_2(bb_candidate, a_square1, b_square1, a_square2, b_square2, center_square);
-                    check_pattern_3_and_4(bb_candidate, b_square1, b_square2, a_square1, a_square2, center_square, magic_sum, center_sum);
-                }
+            let ba_candidate = b_remainder - a_square2;
+            if is_square(ba_candidate) {
+                check_pattern_3_and_4(ba_candidate, a_square1, b_square2, center_square, c, report);
             }
+        }
 
-            // TODO: pattern 6
+        for (&a_square2, &b_square2) in other_a_squares[..b_upto].iter().zip(&other_b_squares[..b_upto]) {
+            let bb_candidate = b_remainder - b_square2;
+            if is_square(bb_candidate) {
+                check_pattern_2(b_square1, b_square2, center_square, c, report);
+                check_pattern_3_and_4(bb_candidate, a_square1, a_square2, center_square, c, report);
+            }
         }
+
+        check_pattern_6(i, a_squares, b_squares, center_square, c, report);
     });
 }
 
-fn check_pattern_2(top_middle: u128, a_square1: u128, b_square1: u128, a_square2: u128, b_square2: u128, center_square: u128) {
-    let middle_left = a_square1 - b_square1;
-    if is_square(middle_left) {
-        print(a_square1, top_middle, a_square2, middle_left, center_square, 0, b_square1, 0, b_square2);
-    }
+// The top row is square and its corners belong to different triples so both
+// diagonals are square too. One of the side columns must also be square.
+fn check_pattern_2(top_left: u128, top_right: u128, center_square: u128, c: u64, report: &impl Fn(MagicSquareCandidate)) {
+    let Some(cells) = complete_square(top_left, top_right, center_square) else { return };
+    let [_, _, _, middle_left, _, middle_right, _, _, _] = cells;
 
-    let middle_left = a_square1 - b_square2;
-    if is_square(middle_left) {
-        print(a_square1, top_middle, a_square2, middle_left, center_square, 0, b_square1, 0, b_square2);
+    if is_square(middle_left) || is_square(middle_right) {
+        report(MagicSquareCandidate::new(Pattern::Pattern2, c, cells));
     }
+}
 
-    let middle_left = a_square2 - b_square1;
-    if is_square(middle_left) {
-        print(a_square1, top_middle, a_square2, middle_left, center_square, 0, b_square1, 0, b_square2);
-    }
+// The left column is square and the partners of its lower two squares are in
+// the top right and middle right, which can go either way round. The top middle
+// or the bottom middle must also be square.
+fn check_pattern_3_and_4(top_left: u128, right_square1: u128, right_square2: u128, center_square: u128, c: u64, report: &impl Fn(MagicSquareCandidate)) {
+    for top_right in [right_square1, right_square2] {
+        let Some(cells) = complete_square(top_left, top_right, center_square) else { continue };
+        let [_, top_middle, _, _, _, _, _, bottom_middle, _] = cells;
 
-    let middle_left = a_square2 - b_square2;
-    if is_square(middle_left) {
-        print(a_square1, top_middle, a_square2, middle_left, center_square, 0, b_square1, 0, b_square2);
+        if is_square(top_middle) {
+            report(MagicSquareCandidate::new(Pattern::Pattern3, c, cells));
+        } else if is_square(bottom_middle) {
+            report(MagicSquareCandidate::new(Pattern::Pattern4, c, cells));
+        }
     }
 }
 
-fn check_pattern_3_and_4(top_left: u128, left_square1: u128, left_square2: u128, right_square1: u128, right_square2: u128, center_square: u128, magic_sum: u128, center_sum: u128) {
-    let top_middle = magic_sum - top_left - right_square1;
-    if is_square(top_middle) {
-        print(top_left, top_middle, right_square1, left_square2, center_square, right_square2, left_square1, 0, 0);
-    } else {
-        let bottom_middle = center_sum - top_middle;
-        if is_square(bottom_middle) {
-            print(top_left, 0, right_square1, left_square2, center_square, right_square2, left_square1, bottom_middle, 0);
-        }
-    }
+// A magic square is determined by its center and two of its corners. Returns
+// None if any of the other cells wouldn't be positive.
+fn complete_square(top_left: u128, top_right: u128, center_square: u128) -> Option<[u128; 9]> {
+    let center_sum = center_square + center_square;
+    let magic_sum = center_sum + center_square;
 
-    let top_middle = magic_sum - top_left - right_square2;
-    if is_square(top_middle) {
-        print(top_left, top_middle, right_square2, left_square1, center_square, right_square1, left_square2, 0, 0);
-    } else {
-        let bottom_middle = center_sum - top_middle;
-        if is_square(bottom_middle) {
-            print(top_left, 0, right_square1, left_square2, center_square, right_square2, left_square1, bottom_middle, 0);
+    let top_middle = magic_sum.checked_sub(top_left)?.checked_sub(top_right)?;
+    let bottom_left = center_sum.checked_sub(top_right)?;
+    let bottom_middle = center_sum.checked_sub(top_middle)?;
+    let bottom_right = center_sum.checked_sub(top_left)?;
+    let middle_left = magic_sum.checked_sub(top_left)?.checked_sub(bottom_left)?;
+    let middle_right = center_sum.checked_sub(middle_left)?;
+
+    let cells = [top_left, top_middle, top_right, middle_left, center_square, middle_right, bottom_left, bottom_middle, bottom_right];
+    if cells.contains(&0) { None } else { Some(cells) }
+}
+
+// Put the triple i on the diagonal with its larger square in the top left and
+// try each other triple j in the middle row. The top middle is then determined
+// and it must belong to a third triple k for the middle column to be square.
+// Reflecting in the diagonal swaps the middle row and column so only report
+// j < k to find each square once.
+fn check_pattern_6(i: usize, a_squares: &[u128], b_squares: &[u128], center_square: u128, c: u64, report: &impl Fn(MagicSquareCandidate)) {
+    let center_sum = center_square + center_square;
+    let magic_sum = center_sum + center_square;
+
+    let top_left = a_squares[i];
+    let bottom_right = b_squares[i];
+
+    for (j, (&a_square, &b_square)) in a_squares.iter().zip(b_squares).enumerate() {
+        if j == i { continue; }
+
+        for (middle_left, middle_right) in [(a_square, b_square), (b_square, a_square)] {
+            let Some(top_middle) = (bottom_right + middle_right).checked_sub(top_left) else { continue };
+            let Some(k) = find_triple(top_middle, a_squares, b_squares) else { continue };
+            if k <= j || k == i { continue; }
+
+            // Like complete_square, reject the cells that wouldn't be positive.
+            let Some(top_right) = magic_sum.checked_sub(bottom_right + middle_right).filter(|&cell| cell > 0) else { continue };
+            let Some(bottom_left) = center_sum.checked_sub(top_right).filter(|&cell| cell > 0) else { continue };
+            let bottom_middle = center_sum - top_middle;
+
+            report(MagicSquareCandidate::new(Pattern::Pattern6, c, [top_left, top_middle, top_right, middle_left, center_square, middle_right, bottom_left, bottom_middle, bottom_right]));
         }
     }
 }
 
+// The a_squares are in increasing order so the b_squares are decreasing.
+fn find_triple(square: u128, a_squares: &[u128], b_squares: &[u128]) -> Option<usize> {
+    a_squares.binary_search(&square).ok().or_else(|| b_squares.binary_search_by(|probe| square.cmp(probe)).ok())
+}
+
 const MAX_U64: u128 = u64::MAX as u128;
 
-fn is_square(n: u128) -> bool {
+// Entry r is true when r is a square modulo M.
+const fn square_residues<const M: usize>() -> [bool; M] {
+    let mut residues = [false; M];
+    let mut i = 0;
+
+    while i < M {
+        residues[i * i % M] = true;
+        i += 1;
+    }
+
+    residues
+}
+
+const SQUARES_MOD_43_47: [bool; 43 * 47] = square_residues();
+const SQUARES_MOD_67_79: [bool; 67 * 79] = square_residues();
+const SQUARES_MOD_31_71: [bool; 31 * 71] = square_residues();
+
+// Almost none of the numbers we test are squares, so rule most of them out by
+// their residues before taking the root. The numbers come from odd squares and
+// a center made of primes that are 1 mod 4, which makes them 1 mod 8 and mod 3
+// and likely residues modulo the primes of the center, so the usual tables
+// modulo 64, 63 and 65 hardly reject any. Primes that are 3 mod 4 never divide
+// the center and each reject about 45% of them, and the three tables together
+// let under 3% through. They are combined without branching since the
+// outcome of each one is unpredictable.
+pub fn is_square(n: u128) -> bool {
+    let is_residue = SQUARES_MOD_43_47[residue::<{ 43 * 47 }>(n)] & SQUARES_MOD_67_79[residue::<{ 67 * 79 }>(n)] & SQUARES_MOD_31_71[residue::<{ 31 * 71 }>(n)];
+    is_residue && is_square_by_root(n)
+}
+
+// Avoid dividing a u128, which is much slower than dividing a u64 by a constant.
+#[inline(always)]
+fn residue<const M: u64>(n: u128) -> usize {
+    let (high, low) = ((n >> 64) as u64, n as u64);
+    let residue = if high == 0 { low % M } else { (high % M * (u64::MAX % M + 1) + low % M) % M };
+    residue as usize
+}
+
+fn is_square_by_root(n: u128) -> bool {
     if n <= MAX_U64 {
         let n = n as u64;
         let root = n.isqrt();
@@ -122,12 +217,197 @@ fn is_square(n: u128) -> bool {
     }
 }
 
-pub fn print(top_left: u128, top_middle: u128, top_right: u128, middle_left: u128, middle_middle: u128, middle_right: u128, bottom_left: u128, bottom_middle: u128, bottom_right: u128) {
-    println!("----------------------------------------------------------------------------------------------------");
-    println!("| {top_left:^30} | {top_middle:^30} | {top_right:^30} |");
-    println!("|--------------------------------------------------------------------------------------------------|");
-    println!("| {middle_left:^30} | {middle_middle:^30} | {middle_right:^30} |");
-    println!("|--------------------------------------------------------------------------------------------------|");
-    println!("| {bottom_left:^30} | {bottom_middle:^30} | {bottom_right:^30} |");
-    println!("----------------------------------------------------------------------------------------------------\n\n");
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{canonical_orientation, check_squared_triples, verify, CompositeNumber, Verdict, SALLOWS};
+    use rayon::prelude::*;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    // Stand-ins for the magic triples of a center: pairs (c² + d, c² - d). They
+    // aren't squares but the checkers only rely on each pair summing to 2c², so
+    // they contain many more pattern 6 arrangements than real centers do.
+    fn synthetic_squares(c: u64, differences: impl Iterator<Item = u128>) -> (Vec<u128>, Vec<u128>) {
+        let center_square = c as u128 * c as u128;
+        differences.map(|d| (center_square + d, center_square - d)).unzip()
+    }
+
+    // No real center below 3,000,000 has a pattern 6 square, so the checkers
+    // are compared on the stand-ins instead.
+    #[test]
+    fn it_finds_the_same_pattern_6_squares_as_check_patterns_1_and_6() {
+        let c = 1000;
+        let center_square = c as u128 * c as u128;
+        let (a_squares, b_squares) = synthetic_squares(c, (1..=40).map(|d| d * 3));
+
+        // Treat the triples with an even difference as non-primitive.
+        let is_primitive = |a_square: u128| (a_square - center_square) % 2 == 1;
+        let triples = a_squares.iter().copied().zip(b_squares.iter().copied());
+        let (primitive, non_primitive): (Vec<_>, Vec<_>) = triples.partition(|&(a_square, _)| is_primitive(a_square));
+
+        let patterns_16_hits = Mutex::new(vec![]);
+        check_squared_triples(&non_primitive, &primitive, c, &|candidate| if candidate.pattern == Pattern::Pattern6 { patterns_16_hits.lock().unwrap().push(canonical_orientation(candidate.cells)); });
+
+        let patterns_234_hits = Mutex::new(vec![]);
+        for i in 0..a_squares.len() {
+            check_pattern_6(i, &a_squares, &b_squares, center_square, c, &|candidate| patterns_234_hits.lock().unwrap().push(candidate.cells));
+        }
+
+        let mut patterns_16_hits = patterns_16_hits.into_inner().unwrap();
+        patterns_16_hits.sort();
+        patterns_16_hits.dedup();
+
+        let patterns_234_hits = patterns_234_hits.into_inner().unwrap();
+        let mut canonical_hits = patterns_234_hits.iter().map(|&cells| canonical_orientation(cells)).collect::<Vec<_>>();
+        canonical_hits.sort();
+        canonical_hits.dedup();
+
+        assert!(!patterns_16_hits.is_empty());
+        assert_eq!(patterns_16_hits, canonical_hits);
+    }
+
+    // All of the magic triples of a center, not just those that come from
+    // primitive Pythagorean triples.
+    fn magic_squares(c: u64) -> (Vec<u128>, Vec<u128>) {
+        let center_square = c as u128 * c as u128;
+        let a_roots = (c as u128 + 1..(2 * center_square).isqrt() + 1).filter(|a| is_square(2 * center_square - a * a));
+        a_roots.map(|a| (a * a, 2 * center_square - a * a)).unzip()
+    }
+
+    #[test]
+    fn it_reports_every_cell_of_sallows_square() {
+        let c = 425;
+        let (a_squares, b_squares) = magic_squares(c);
+
+        let hits = Mutex::new(vec![]);
+        check_squares(&a_squares, &b_squares, c, is_square, false, &|candidate| hits.lock().unwrap().push(candidate));
+        let hits = hits.into_inner().unwrap();
+
+        assert!(hits.iter().any(|candidate| candidate.pattern == Pattern::Pattern4 && canonical_orientation(candidate.cells) == canonical_orientation(SALLOWS)));
+
+        for candidate in hits {
+            assert!(!candidate.cells.contains(&0));
+            assert_eq!(candidate.squares.iter().filter(|&&s| s).count(), 7);
+            assert_eq!(verify(&candidate), Verdict::NearMiss);
+        }
+    }
+
+    #[test]
+    fn it_reports_pattern_6_squares_with_square_center_lines() {
+        let c = 1000;
+        let (a_squares, b_squares) = synthetic_squares(c, (1..=40).map(|d| d * 3));
+        let center_square = c as u128 * c as u128;
+
+        let hits = Mutex::new(vec![]);
+        for i in 0..a_squares.len() {
+            check_pattern_6(i, &a_squares, &b_squares, center_square, c, &|candidate| hits.lock().unwrap().push(candidate.cells));
+        }
+
+        for cells in hits.into_inner().unwrap() {
+            let [top_left, top_middle, top_right, middle_left, middle_middle, middle_right, bottom_left, bottom_middle, bottom_right] = cells;
+            let magic_sum = 3 * center_square;
+
+            assert_eq!(middle_middle, center_square);
+            assert!(find_triple(top_left, &a_squares, &b_squares).is_some() && top_left + bottom_right == 2 * center_square);
+            assert!(find_triple(middle_left, &a_squares, &b_squares).is_some() && middle_left + middle_right == 2 * center_square);
+            assert!(find_triple(top_middle, &a_squares, &b_squares).is_some() && top_middle + bottom_middle == 2 * center_square);
+
+            assert_eq!(top_left + top_middle + top_right, magic_sum);
+            assert_eq!(bottom_left + bottom_middle + bottom_right, magic_sum);
+            assert_eq!(top_left + middle_left + bottom_left, magic_sum);
+            assert_eq!(top_right + middle_right + bottom_right, magic_sum);
+            assert_eq!(top_right + middle_middle + bottom_left, magic_sum);
+        }
+    }
+
+    #[test]
+    fn it_agrees_with_the_root_about_which_numbers_are_square() {
+        let small = 0..100_000;
+        let near_large_squares = [1 << 31, (1 << 32) - 1, 1 << 32, 1 << 40, 1 << 62].into_iter().flat_map(|root: u128| (root - 100..root + 100).flat_map(|r| [r * r - 1, r * r, r * r + 1]));
+
+        for n in small.chain(near_large_squares) {
+            assert_eq!(is_square(n), is_square_by_root(n), "{n}");
+        }
+    }
+
+    // The magic triples of the center, sorted by a.
+    fn real_triples(c: u64) -> (Vec<u64>, Vec<u64>) {
+        let mut composite_number = CompositeNumber::for_center(c, vec![TripleOrder::ByA]).unwrap();
+        let triples = Mutex::new((vec![], vec![]));
+
+        composite_number.for_each_in_search_range(|t| {
+            let mut triples = triples.lock().unwrap();
+            triples.0.extend_from_slice(t.a_values);
+            triples.1.extend_from_slice(t.b_values);
+        });
+
+        triples.into_inner().unwrap()
+    }
+
+    fn real_squares(c: u64) -> (Vec<u128>, Vec<u128>) {
+        let (a_values, b_values) = real_triples(c);
+        (a_values.iter().map(|&a| a as u128 * a as u128).collect(), b_values.iter().map(|&b| b as u128 * b as u128).collect())
+    }
+
+    fn real_hits(c: u64, is_square: impl Fn(u128) -> bool + Sync) -> Vec<(Pattern, [u128; 9])> {
+        let (a_squares, b_squares) = real_squares(c);
+        let hits = Mutex::new(vec![]);
+        check_squares(&a_squares, &b_squares, c, is_square, false, &|candidate| hits.lock().unwrap().push((candidate.pattern, candidate.cells)));
+        hits.into_inner().unwrap()
+    }
+
+    #[test]
+    fn it_finds_the_same_squares_with_the_prefilter_on_real_centers() {
+        for c in [425, 5 * 5 * 13 * 17 * 29 * 37] {
+            assert_eq!(real_hits(c, is_square), real_hits(c, is_square_by_root));
+        }
+
+        assert!(!real_hits(425, is_square).is_empty());
+    }
+
+    #[test]
+    fn it_splits_the_outer_loop_of_a_large_center_without_changing_the_squares() {
+        let c = 5 * 5 * 13 * 17 * 29 * 37 * 41;
+        let (a_values, b_values) = real_triples(c);
+        assert!(a_values.len() >= PARALLEL_MIN_TRIPLES);
+
+        let mut sequential = real_hits(c, is_square);
+        sequential.sort();
+        assert!(!sequential.is_empty());
+
+        // Several large centers at once, as in a batch.
+        let batch = (0..4).into_par_iter().map(|_| {
+            let hits = Mutex::new(vec![]);
+            check_patterns_2_3_and_4(&a_values, &b_values, c, &|candidate| hits.lock().unwrap().push((candidate.pattern, candidate.cells)));
+
+            let mut hits = hits.into_inner().unwrap();
+            hits.sort();
+            hits
+        }).collect::<Vec<_>>();
+
+        assert!(batch.iter().all(|hits| *hits == sequential));
+    }
+
+    // cargo test --release -- --ignored --nocapture it_benchmarks
+    //
+    // The prefilter took 59-65% of the time of the root on each of these
+    // centers, e.g. 70.3ms against 113ms for the 1,093 triples of the last.
+    #[test]
+    #[ignore]
+    fn it_benchmarks_the_prefilter_against_the_root() {
+        for c in [5 * 5 * 13 * 17 * 29 * 37, 5 * 13 * 17 * 29 * 37 * 41, 5 * 5 * 13 * 13 * 17 * 29 * 37, 5 * 13 * 17 * 29 * 37 * 41 * 53] {
+            let (a_squares, b_squares) = real_squares(c);
+            assert_eq!(real_hits(c, is_square), real_hits(c, is_square_by_root));
+
+            let time = |is_square: fn(u128) -> bool| {
+                let start = Instant::now();
+                for _ in 0..10 { check_squares(&a_squares, &b_squares, c, is_square, false, &|_| {}); }
+                start.elapsed() / 10
+            };
+
+            let (root_time, prefilter_time) = (time(is_square_by_root), time(is_square));
+            println!("center {c} with {} triples: root {root_time:?}, prefilter {prefilter_time:?}", a_squares.len());
+        }
+    }
 }
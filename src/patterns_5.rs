@@ -0,0 +1,125 @@
+use crate::{MagicSquareCandidate, Pattern, PatternChecker, MagicTriples, TripleOrder, is_square};
+use std::cell::RefCell;
+
+// The patterns are from figure 5 of http://www.multimagie.com/Search.pdf#page=2
+//
+// Pattern 5 isn't part of the default search. Its two non-square cells are
+// adjacent corners so the only square lines through the center are the middle
+// row and column, and the remaining square line is the row opposite the
+// non-square corners.
+
+thread_local! {
+    static SQUARES: RefCell<(Vec<u128>, Vec<u128>)> = const { RefCell::new((vec![], vec![])) };
+}
+
+pub struct Patterns5Checker;
+
+impl PatternChecker for Patterns5Checker {
+    fn name(&self) -> &'static str { "patterns5" }
+    fn triple_order(&self) -> TripleOrder { TripleOrder::ByA }
+    fn min_triples(&self) -> usize { 2 }
+
+    fn check(&self, triples: &MagicTriples, report: &(dyn Fn(MagicSquareCandidate) + Sync)) {
+        check_pattern_5(triples.a_values, triples.b_values, triples.center, &report);
+    }
+}
+
+pub fn check_pattern_5(a_values: &[u64], b_values: &[u64], c: u64, report: &impl Fn(MagicSquareCandidate)) {
+    SQUARES.with_borrow_mut(|(a_squares, b_squares)| {
+        a_squares.clear();
+        a_squares.extend(a_values.iter().map(|&a| { let a = a as u128; a * a }));
+
+        b_squares.clear();
+        b_squares.extend(b_values.iter().map(|&b| { let b = b as u128; b * b }));
+
+        let center = c as u128;
+        check_squares(a_squares, b_squares, center * center, c, is_square, report);
+    });
+}
+
+// Put the triple j on the middle row and one square of the triple k in the
+// bottom middle. The bottom corners are then determined by the bottom row and
+// the left column, and both must be square. Reflecting the grid left to right
+// swaps the middle row so only the larger square of j goes on the left.
+fn check_squares(a_squares: &[u128], b_squares: &[u128], center_square: u128, c: u64, is_square: impl Fn(u128) -> bool, report: &impl Fn(MagicSquareCandidate)) {
+    let center_sum = center_square + center_square;
+    let magic_sum = center_sum + center_square;
+
+    for (j, (&middle_left, &middle_right)) in a_squares.iter().zip(b_squares).enumerate() {
+        for (k, (&a_square, &b_square)) in a_squares.iter().zip(b_squares).enumerate() {
+            if j == k { continue; }
+
+            for bottom_middle in [a_square, b_square] {
+                // bottom_left + bottom_right = magic_sum - bottom_middle and
+                // bottom_right - bottom_left = middle_left - center_square. Both
+                // sides are kept below the magic sum so they can't overflow.
+                let Some(twice_bottom_left) = (magic_sum - bottom_middle).checked_sub(middle_left - center_square) else { continue };
+                if twice_bottom_left % 2 != 0 { continue; }
+
+                let bottom_left = twice_bottom_left / 2;
+                let bottom_right = magic_sum - bottom_middle - bottom_left;
+                if bottom_left == 0 || bottom_left >= center_sum || bottom_right >= center_sum { continue; }
+                if !is_square(bottom_left) || !is_square(bottom_right) { continue; }
+
+                let top_left = center_sum - bottom_right;
+                let top_middle = center_sum - bottom_middle;
+                let top_right = center_sum - bottom_left;
+
+                report(MagicSquareCandidate::new(Pattern::Pattern5, c, [top_left, top_middle, top_right, middle_left, center_square, middle_right, bottom_left, bottom_middle, bottom_right]));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    const C: u128 = 1_000_000;
+
+    // A hand-made magic square around C with the pattern 5 arrangement. Its
+    // values aren't squares so the test decides which of them count as square.
+    const GRID: [u128; 9] = [
+        C - 90,  C - 120, C + 210,
+        C + 300, C,       C - 300,
+        C - 210, C + 120, C + 90,
+    ];
+
+    fn hits(differences: &[u128], squares: &[u128]) -> Vec<[u128; 9]> {
+        let (a_squares, b_squares): (Vec<_>, Vec<_>) = differences.iter().map(|d| (C + d, C - d)).unzip();
+
+        let hits = Mutex::new(vec![]);
+        check_squares(&a_squares, &b_squares, C, 1000, |n| squares.contains(&n), &|candidate| hits.lock().unwrap().push(candidate.cells));
+        hits.into_inner().unwrap()
+    }
+
+    #[test]
+    fn it_finds_a_hand_made_pattern_5_grid() {
+        let bottom_corners = [GRID[6], GRID[8]];
+
+        assert_eq!(hits(&[120, 300], &bottom_corners), &[GRID]);
+        assert_eq!(hits(&[7, 120, 180, 300, 450], &bottom_corners), &[GRID]);
+    }
+
+    #[test]
+    fn it_requires_both_bottom_corners_to_be_square() {
+        assert!(hits(&[120, 300], &[GRID[6]]).is_empty());
+        assert!(hits(&[120, 300], &[GRID[8]]).is_empty());
+    }
+
+    #[test]
+    fn it_reports_grids_whose_lines_sum_to_the_magic_sum() {
+        let differences = (1..=60).map(|d| d * 10).collect::<Vec<_>>();
+        let all_values = differences.iter().flat_map(|d| [C + d, C - d]).collect::<Vec<_>>();
+
+        let hits = hits(&differences, &all_values);
+        assert!(hits.contains(&GRID));
+
+        for cells in hits {
+            for [i, j, k] in [[0, 1, 2], [3, 4, 5], [6, 7, 8], [0, 3, 6], [1, 4, 7], [2, 5, 8], [0, 4, 8], [2, 4, 6]] {
+                assert_eq!(cells[i] + cells[j] + cells[k], 3 * C);
+            }
+        }
+    }
+}
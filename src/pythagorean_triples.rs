@@ -1,9 +1,10 @@
 use fast_modulo::powmod_u64 as modular_exponentiation;
 use rayon::prelude::*;
-use std::simd::Simd;
-use std::simd::num::SimdUint;
-use std::simd::cmp::SimdPartialEq;
+#[cfg(feature = "simd")]
+use std::simd::{Simd, Select, cmp::SimdPartialEq, num::SimdUint};
+use std::ops::Range;
 
+#[derive(Clone, Default)]
 pub struct PythagoreanTriples {
     pub a_values: Vec<u64>,
     pub b_values: Vec<u64>,
@@ -22,29 +23,40 @@ pub struct TemporaryBuffer {
     primitive: Vec<(u64, usize)>,
 }
 
+#[cfg(feature = "simd")]
 type SimdU32 = Simd::<u32, { crate::SIMD_LANES }>;
+#[cfg(feature = "simd")]
 type SimdU64 = Simd::<u64, { crate::SIMD_LANES }>;
 
 const TOP_BIT: u32 = 1 << 31;
+#[cfg(feature = "simd")]
 const TOP_BIT_VECTOR: SimdU32 = SimdU32::splat(TOP_BIT);
+#[cfg(feature = "simd")]
 const ZERO_VECTOR: SimdU32 = SimdU32::splat(0);
+#[cfg(feature = "simd")]
 const ONE_VECTOR: SimdU64 = SimdU64::splat(1);
 
+const SEGMENT_LEN: u64 = 1 << 18;
+
 impl PythagoreanTriples {
     pub fn new(num_primes: usize) -> Self {
-        if num_primes != 0 { println!("Computing the first {num_primes} primitive Pythagorean triples."); }
+        if num_primes != 0 { eprintln!("Computing the first {num_primes} primitive Pythagorean triples."); }
+
+        let primes = primal::Primes::all().filter(|p| p % 4 == 1).take(num_primes).map(|p| p as u64);
+        Self::for_primes(primes, num_primes)
+    }
 
+    pub fn for_primes(mut primes: impl Iterator<Item = u64>, num_primes: usize) -> Self {
         let mut a_values = Vec::with_capacity(num_primes);
         let mut b_values = Vec::with_capacity(num_primes);
         let mut c_values = Vec::with_capacity(num_primes);
 
-        let mut primes = primal::Primes::all().filter(|p| p % 4 == 1).take(num_primes);
         let mut chunk = Vec::with_capacity(10_000);
         let mut tuples = Vec::with_capacity(10_000);
 
         loop {
             chunk.clear();
-            chunk.extend(primes.by_ref().map(|p| p as u64).take(chunk.capacity()));
+            chunk.extend(primes.by_ref().take(chunk.capacity()));
             if chunk.is_empty() { break; }
 
             tuples.par_extend(chunk.par_iter().map(|&p| (Self::compute(p), p)));
@@ -59,6 +71,61 @@ impl PythagoreanTriples {
         Self { a_values, b_values, c_values, factors: vec![] }
     }
 
+    // Compute the triples for the Pythagorean primes in a window with a
+    // segmented sieve. Only the primes up to the square root of the end of the
+    // window are kept in memory and each batch of segments is sieved in parallel.
+    pub fn for_window(window: Range<u64>) -> Self {
+        let sieve_limit = window.end.saturating_sub(1).isqrt() as usize;
+
+        // Sieving the base primes would cost more than the window itself so test
+        // each candidate instead, e.g. for the final terms of large prefixes.
+        if window.end - window.start.min(window.end) < sieve_limit as u64 {
+            let primes = window.filter(|&n| n % 4 == 1 && primal::is_prime(n));
+            return Self::for_primes(primes, 0);
+        }
+
+        let base_primes = primal::Sieve::new(sieve_limit).primes_from(0).take_while(|&p| p <= sieve_limit).map(|p| p as u64).collect::<Vec<_>>();
+
+        let segments = (window.start..window.end).step_by(SEGMENT_LEN as usize).map(|start| start..window.end.min(start.saturating_add(SEGMENT_LEN)));
+        let batch_len = rayon::current_num_threads() * 4;
+
+        let mut triples = Self::new(0);
+        let mut segments = segments.peekable();
+        let mut batch = Vec::with_capacity(batch_len);
+
+        while segments.peek().is_some() {
+            batch.clear();
+            batch.extend(segments.by_ref().take(batch_len));
+
+            let batch_triples = batch.par_iter().map(|segment| {
+                Self::sieve_segment(segment.clone(), &base_primes).into_iter().map(|p| (Self::compute(p), p)).collect::<Vec<_>>()
+            }).collect::<Vec<_>>();
+
+            for ((a, b), c) in batch_triples.into_iter().flatten() {
+                triples.a_values.push(a);
+                triples.b_values.push(b);
+                triples.c_values.push(c);
+            }
+        }
+
+        triples
+    }
+
+    fn sieve_segment(segment: Range<u64>, base_primes: &[u64]) -> Vec<u64> {
+        let mut is_composite = vec![false; (segment.end - segment.start) as usize];
+
+        for &p in base_primes {
+            if p * p >= segment.end { break; }
+
+            let first_multiple = (p * p).max(segment.start.div_ceil(p) * p);
+            for multiple in (first_multiple..segment.end).step_by(p as usize) {
+                is_composite[(multiple - segment.start) as usize] = true;
+            }
+        }
+
+        segment.zip(is_composite).filter(|&(n, is_composite)| n % 4 == 1 && n > 1 && !is_composite).map(|(n, _)| n).collect()
+    }
+
     pub fn len(&self) -> usize {
         self.a_values.len()
     }
@@ -103,49 +170,30 @@ impl PythagoreanTriples {
 
     // Apply the Brahmagupta–Fibonacci identity to combine two Pythagorean triples
     // into two new primitive Pythagorean triples for the product of hypotenuses.
-    pub fn product(&self, (x, y, z, g): (u64, u64, u64, u32), output: &mut Self) {
+    // Each of ax, ay, bx, by and their sums are at most cz so the SIMD lanes
+    // can't wrap as long as the product of hypotenuses fits in a u64.
+    pub fn product(&self, triple: (u64, u64, u64, u32), output: &mut Self) {
+        #[cfg(feature = "simd")]
+        self.product_by_chunks(triple, output, Self::product_of_simd_chunk);
+        #[cfg(not(feature = "simd"))]
+        self.product_by_chunks(triple, output, Self::product_of_scalar_chunk);
+    }
+
+    // Each chunk of SIMD_LANES triples writes the first of its products to one
+    // block of the output and the second to the next, whichever path is used,
+    // so both give the same triples in the same order.
+    fn product_by_chunks(&self, (x, y, z, g): (u64, u64, u64, u32), output: &mut Self, product_of_chunk: impl Fn(&Self, (u64, u64, u64, u32), usize, usize, &mut Self)) {
+        debug_assert!(self.c_values.iter().all(|c| c.checked_mul(z).is_some()), "The product of hypotenuses overflows a u64.");
+
         let num_triples = self.len();
         let existing_len = output.len();
         output.resize(existing_len + num_triples * 2, 0);
 
-        let x_vector = SimdU64::splat(x);
-        let y_vector = SimdU64::splat(y);
-        let z_vector = SimdU64::splat(z);
-        let g_vector = SimdU32::splat(g);
-
         let remainder = num_triples % crate::SIMD_LANES;
         let simd_end = num_triples - remainder;
 
         for chunk_start in (0..simd_end).step_by(crate::SIMD_LANES) {
-            let chunk_end = chunk_start + crate::SIMD_LANES;
-            let a_vector = SimdU64::from_slice(&self.a_values[chunk_start..chunk_end]);
-            let b_vector = SimdU64::from_slice(&self.b_values[chunk_start..chunk_end]);
-            let c_vector = SimdU64::from_slice(&self.c_values[chunk_start..chunk_end]);
-            let f_vector = SimdU32::from_slice(&self.factors[chunk_start..chunk_end]);
-
-            let ax_vector = a_vector * x_vector;
-            let ay_vector = a_vector * y_vector;
-            let bx_vector = b_vector * x_vector;
-            let by_vector = b_vector * y_vector;
-            let cz_vector = c_vector * z_vector;
-
-            let is_non_primitive = (f_vector & g_vector).simd_ne(ZERO_VECTOR);
-            let non_primitive_flag = is_non_primitive.select(TOP_BIT_VECTOR, ZERO_VECTOR);
-            let factors_vector = non_primitive_flag | f_vector | g_vector;
-
-            let first_slot = existing_len + chunk_start * 2;
-            let second_slot = first_slot + crate::SIMD_LANES;
-            let second_slot_end = second_slot + crate::SIMD_LANES;
-
-            ax_vector.abs_diff(by_vector).copy_to_slice(&mut output.a_values[first_slot..second_slot]);
-            (ay_vector + bx_vector).copy_to_slice(&mut output.b_values[first_slot..second_slot]);
-            cz_vector.copy_to_slice(&mut output.c_values[first_slot..second_slot]);
-            factors_vector.copy_to_slice(&mut output.factors[first_slot..second_slot]);
-
-            (ax_vector + by_vector).copy_to_slice(&mut output.a_values[second_slot..second_slot_end]);
-            ay_vector.abs_diff(bx_vector).copy_to_slice(&mut output.b_values[second_slot..second_slot_end]);
-            cz_vector.copy_to_slice(&mut output.c_values[second_slot..second_slot_end]);
-            factors_vector.copy_to_slice(&mut output.factors[second_slot..second_slot_end]);
+            product_of_chunk(self, (x, y, z, g), chunk_start, existing_len + chunk_start * 2, output);
         }
 
         for i in simd_end..num_triples {
@@ -179,6 +227,83 @@ impl PythagoreanTriples {
         }
     }
 
+    #[cfg(feature = "simd")]
+    fn product_of_simd_chunk(&self, (x, y, z, g): (u64, u64, u64, u32), chunk_start: usize, first_slot: usize, output: &mut Self) {
+        let x_vector = SimdU64::splat(x);
+        let y_vector = SimdU64::splat(y);
+        let z_vector = SimdU64::splat(z);
+        let g_vector = SimdU32::splat(g);
+
+        let chunk_end = chunk_start + crate::SIMD_LANES;
+        let a_vector = SimdU64::from_slice(&self.a_values[chunk_start..chunk_end]);
+        let b_vector = SimdU64::from_slice(&self.b_values[chunk_start..chunk_end]);
+        let c_vector = SimdU64::from_slice(&self.c_values[chunk_start..chunk_end]);
+        let f_vector = SimdU32::from_slice(&self.factors[chunk_start..chunk_end]);
+
+        let ax_vector = a_vector * x_vector;
+        let ay_vector = a_vector * y_vector;
+        let bx_vector = b_vector * x_vector;
+        let by_vector = b_vector * y_vector;
+        let cz_vector = c_vector * z_vector;
+
+        let is_non_primitive = (f_vector & g_vector).simd_ne(ZERO_VECTOR);
+        let non_primitive_flag = is_non_primitive.select(TOP_BIT_VECTOR, ZERO_VECTOR);
+        let factors_vector = non_primitive_flag | f_vector | g_vector;
+
+        let second_slot = first_slot + crate::SIMD_LANES;
+        let second_slot_end = second_slot + crate::SIMD_LANES;
+
+        ax_vector.abs_diff(by_vector).copy_to_slice(&mut output.a_values[first_slot..second_slot]);
+        (ay_vector + bx_vector).copy_to_slice(&mut output.b_values[first_slot..second_slot]);
+        cz_vector.copy_to_slice(&mut output.c_values[first_slot..second_slot]);
+        factors_vector.copy_to_slice(&mut output.factors[first_slot..second_slot]);
+
+        (ax_vector + by_vector).copy_to_slice(&mut output.a_values[second_slot..second_slot_end]);
+        ay_vector.abs_diff(bx_vector).copy_to_slice(&mut output.b_values[second_slot..second_slot_end]);
+        cz_vector.copy_to_slice(&mut output.c_values[second_slot..second_slot_end]);
+        factors_vector.copy_to_slice(&mut output.factors[second_slot..second_slot_end]);
+    }
+
+    // The same as the SIMD chunk but written over fixed-length slices so that
+    // the compiler can drop the bounds checks and vectorize it on stable Rust.
+    #[cfg(any(test, not(feature = "simd")))]
+    fn product_of_scalar_chunk(&self, (x, y, z, g): (u64, u64, u64, u32), chunk_start: usize, first_slot: usize, output: &mut Self) {
+        const LANES: usize = crate::SIMD_LANES;
+
+        let chunk_end = chunk_start + LANES;
+        let a_values = &self.a_values[chunk_start..chunk_end];
+        let b_values = &self.b_values[chunk_start..chunk_end];
+        let c_values = &self.c_values[chunk_start..chunk_end];
+        let f_values = &self.factors[chunk_start..chunk_end];
+
+        let (first_a, second_a) = output.a_values[first_slot..first_slot + 2 * LANES].split_at_mut(LANES);
+        let (first_b, second_b) = output.b_values[first_slot..first_slot + 2 * LANES].split_at_mut(LANES);
+        let (first_c, second_c) = output.c_values[first_slot..first_slot + 2 * LANES].split_at_mut(LANES);
+        let (first_f, second_f) = output.factors[first_slot..first_slot + 2 * LANES].split_at_mut(LANES);
+
+        for lane in 0..LANES {
+            let ax = a_values[lane] * x;
+            let ay = a_values[lane] * y;
+            let bx = b_values[lane] * x;
+            let by = b_values[lane] * y;
+            let cz = c_values[lane] * z;
+
+            let f = f_values[lane];
+            let non_primitive_flag = (f & g != 0) as u32 * TOP_BIT;
+            let factors = non_primitive_flag | f | g;
+
+            first_a[lane] = ax.abs_diff(by);
+            first_b[lane] = ay + bx;
+            first_c[lane] = cz;
+            first_f[lane] = factors;
+
+            second_a[lane] = ax + by;
+            second_b[lane] = ay.abs_diff(bx);
+            second_c[lane] = cz;
+            second_f[lane] = factors;
+        }
+    }
+
     pub fn remove_trivial(&mut self, buffer: &mut TemporaryBuffer) {
         buffer.indexes.clear();
         self.b_values.iter().enumerate().for_each(|(i, &b)| if b != 0 { buffer.indexes.push(i); });
@@ -265,29 +390,24 @@ impl PythagoreanTriples {
 
     // We can parameterize pythagorean triples with x=a+b and y=|a-b| to find
     // solutions to a^2 + b^2 = 2c^2 which is what we care about for magic squares.
+    // Both are at most c√2 so they can't overflow below the MAX_CENTER ceiling.
     #[allow(clippy::wrong_self_convention)]
     pub fn into_magic_triples(&mut self, final_product: u64) {
+        #[cfg(feature = "simd")]
+        self.magic_triples_by_chunks(final_product, Self::magic_triples_of_simd_chunk);
+        #[cfg(not(feature = "simd"))]
+        self.magic_triples_by_chunks(final_product, Self::magic_triples_of_scalar_chunk);
+    }
+
+    fn magic_triples_by_chunks(&mut self, final_product: u64, magic_triples_of_chunk: impl Fn(&mut Self, u64, usize)) {
+        debug_assert!(final_product <= crate::MAX_CENTER, "The magic triples of {final_product} can overflow a u64.");
+
         let num_triples = self.len();
         let remainder = num_triples % crate::SIMD_LANES;
         let simd_end = num_triples - remainder;
-        let final_product_vector = SimdU64::splat(final_product);
 
         for chunk_start in (0..simd_end).step_by(crate::SIMD_LANES) {
-            let chunk_end = chunk_start + crate::SIMD_LANES;
-            let a_vector = SimdU64::from_slice(&self.a_values[chunk_start..chunk_end]);
-            let b_vector = SimdU64::from_slice(&self.b_values[chunk_start..chunk_end]);
-            let c_vector = SimdU64::from_slice(&self.c_values[chunk_start..chunk_end]);
-            let f_vector = SimdU32::from_slice(&self.factors[chunk_start..chunk_end]);
-            let scale_vector = final_product_vector / c_vector;
-
-            let is_non_primitive = scale_vector.simd_ne(ONE_VECTOR).cast::<i32>();
-            let non_primitive_flag = is_non_primitive.select(TOP_BIT_VECTOR, ZERO_VECTOR);
-            let factors_vector = non_primitive_flag | f_vector;
-
-            (scale_vector * (a_vector + b_vector)).copy_to_slice(&mut self.a_values[chunk_start..chunk_end]);
-            (scale_vector * a_vector.abs_diff(b_vector)).copy_to_slice(&mut self.b_values[chunk_start..chunk_end]);
-            // Skip setting self.c since the caller can assume it is the final_product.
-            factors_vector.copy_to_slice(&mut self.factors[chunk_start..chunk_end]);
+            magic_triples_of_chunk(self, final_product, chunk_start);
         }
 
         for i in simd_end..num_triples {
@@ -306,6 +426,45 @@ impl PythagoreanTriples {
         }
     }
 
+    #[cfg(feature = "simd")]
+    fn magic_triples_of_simd_chunk(&mut self, final_product: u64, chunk_start: usize) {
+        let final_product_vector = SimdU64::splat(final_product);
+
+        let chunk_end = chunk_start + crate::SIMD_LANES;
+        let a_vector = SimdU64::from_slice(&self.a_values[chunk_start..chunk_end]);
+        let b_vector = SimdU64::from_slice(&self.b_values[chunk_start..chunk_end]);
+        let c_vector = SimdU64::from_slice(&self.c_values[chunk_start..chunk_end]);
+        let f_vector = SimdU32::from_slice(&self.factors[chunk_start..chunk_end]);
+        let scale_vector = final_product_vector / c_vector;
+
+        let is_non_primitive = scale_vector.simd_ne(ONE_VECTOR).cast::<i32>();
+        let non_primitive_flag = is_non_primitive.select(TOP_BIT_VECTOR, ZERO_VECTOR);
+        let factors_vector = non_primitive_flag | f_vector;
+
+        (scale_vector * (a_vector + b_vector)).copy_to_slice(&mut self.a_values[chunk_start..chunk_end]);
+        (scale_vector * a_vector.abs_diff(b_vector)).copy_to_slice(&mut self.b_values[chunk_start..chunk_end]);
+        // Skip setting self.c since the caller can assume it is the final_product.
+        factors_vector.copy_to_slice(&mut self.factors[chunk_start..chunk_end]);
+    }
+
+    #[cfg(any(test, not(feature = "simd")))]
+    fn magic_triples_of_scalar_chunk(&mut self, final_product: u64, chunk_start: usize) {
+        let chunk = chunk_start..chunk_start + crate::SIMD_LANES;
+        let a_values = &mut self.a_values[chunk.clone()];
+        let b_values = &mut self.b_values[chunk.clone()];
+        let c_values = &self.c_values[chunk.clone()];
+        let f_values = &mut self.factors[chunk];
+
+        for lane in 0..crate::SIMD_LANES {
+            let (a, b) = (a_values[lane], b_values[lane]);
+            let scale = final_product / c_values[lane];
+
+            a_values[lane] = scale * (a + b);
+            b_values[lane] = scale * a.abs_diff(b);
+            f_values[lane] |= (scale != 1) as u32 * TOP_BIT;
+        }
+    }
+
     // Use Cornacchia's algorithm to solve a^2 + b^2 = p then apply Euclid's
     // parameterization to find the primitive Pythagorean triple for the prime.
     fn compute(pythagorean_prime: u64) -> (u64, u64) {
@@ -341,8 +500,9 @@ impl PythagoreanTriples {
         loop {
             let remainder = a % b;
 
-            let sum_of_squares = remainder * remainder + b * b;
-            if sum_of_squares == pythagorean_prime { return (b, remainder); }
+            // Widen since b starts near the prime and its square can overflow.
+            let sum_of_squares = remainder as u128 * remainder as u128 + b as u128 * b as u128;
+            if sum_of_squares == pythagorean_prime as u128 { return (b, remainder); }
 
             a = b;
             b = remainder;
@@ -385,6 +545,41 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_can_compute_the_triples_for_a_window_of_primes() {
+        let window = PythagoreanTriples::for_window(100..1000);
+        let expected_primes = primal::Primes::all().map(|p| p as u64).skip_while(|&p| p < 100).take_while(|&p| p < 1000).filter(|p| p % 4 == 1).collect::<Vec<_>>();
+        assert_eq!(window.c_values, expected_primes);
+
+        // Windows that span several segments join up with the first n triples.
+        let triples = PythagoreanTriples::new(30_000);
+        let largest_prime = *triples.c_values.last().unwrap();
+        assert!(largest_prime > 2 * SEGMENT_LEN);
+
+        let mut joined = PythagoreanTriples::for_window(0..1000);
+        joined.extend(&PythagoreanTriples::for_window(1000..largest_prime + 1));
+        assert_eq!(joined.a_values, triples.a_values);
+        assert_eq!(joined.b_values, triples.b_values);
+        assert_eq!(joined.c_values, triples.c_values);
+        assert!(joined.factors.is_empty());
+
+        assert_eq!(PythagoreanTriples::for_window(5..5).len(), 0);
+        assert_eq!(PythagoreanTriples::for_window(0..6).c_values, &[5]);
+    }
+
+    #[test]
+    fn it_tests_each_candidate_in_a_narrow_window_of_large_primes() {
+        let start = 1 << 62;
+        let window = PythagoreanTriples::for_window(start..start + 1000);
+        let expected = (start..start + 1000).filter(|&n| n % 4 == 1 && primal::is_prime(n)).collect::<Vec<_>>();
+
+        assert!(!expected.is_empty());
+        assert_eq!(window.c_values, expected);
+        for ((a, b), c) in window.a_values.iter().zip(&window.b_values).zip(&window.c_values) {
+            assert_eq!(*a as u128 * *a as u128 + *b as u128 * *b as u128, *c as u128 * *c as u128);
+        }
+    }
+
     #[test]
     fn it_can_calculate_the_product_of_primitive_triples() {
         let mut triples = PythagoreanTriples::new(100);
@@ -503,6 +698,83 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg(feature = "simd")]
+    fn it_gets_the_same_triples_from_the_simd_and_scalar_paths() {
+        let mut triples = PythagoreanTriples::new(200);
+        triples.factors = (0..200).map(|i| 1 << (i % 5)).collect();
+
+        let mut simd = PythagoreanTriples::default();
+        let mut scalar = PythagoreanTriples::default();
+        triples.product_by_chunks((5, 12, 13, 0b100), &mut simd, PythagoreanTriples::product_of_simd_chunk);
+        triples.product_by_chunks((5, 12, 13, 0b100), &mut scalar, PythagoreanTriples::product_of_scalar_chunk);
+
+        assert_eq!(simd.len(), 400);
+        assert_eq!(simd.a_values, scalar.a_values);
+        assert_eq!(simd.b_values, scalar.b_values);
+        assert_eq!(simd.c_values, scalar.c_values);
+        assert_eq!(simd.factors, scalar.factors);
+
+        let final_product = *simd.c_values.iter().max().unwrap();
+        simd.magic_triples_by_chunks(final_product, PythagoreanTriples::magic_triples_of_simd_chunk);
+        scalar.magic_triples_by_chunks(final_product, PythagoreanTriples::magic_triples_of_scalar_chunk);
+
+        assert_eq!(simd.a_values, scalar.a_values);
+        assert_eq!(simd.b_values, scalar.b_values);
+        assert_eq!(simd.factors, scalar.factors);
+        assert!(simd.factors.iter().any(|&f| f & TOP_BIT != 0) && simd.factors.iter().any(|&f| f & TOP_BIT == 0));
+    }
+
+    // Runs with or without the simd feature, against whichever path is built.
+    #[test]
+    fn it_gets_the_same_triples_from_the_chunks_as_one_triple_at_a_time() {
+        let mut triples = PythagoreanTriples::new(200);
+        triples.factors = (0..200).map(|i| 1 << (i % 5)).collect();
+        let (x, y, z, g) = (5, 12, 13, 0b100);
+
+        let mut output = PythagoreanTriples::default();
+        triples.product((x, y, z, g), &mut output);
+
+        let mut expected = (0..200).flat_map(|i| {
+            let (a, b, c, f) = (triples.a_values[i], triples.b_values[i], triples.c_values[i], triples.factors[i]);
+            let factors = ((f & g != 0) as u32 * TOP_BIT) | f | g;
+            [((a * x).abs_diff(b * y), a * y + b * x, c * z, factors), (a * x + b * y, (a * y).abs_diff(b * x), c * z, factors)]
+        }).collect::<Vec<_>>();
+
+        let mut actual = (0..output.len()).map(|i| (output.a_values[i], output.b_values[i], output.c_values[i], output.factors[i])).collect::<Vec<_>>();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+
+        let final_product = *output.c_values.iter().max().unwrap();
+        let expected = (0..output.len()).map(|i| {
+            let (a, b, c, f) = (output.a_values[i], output.b_values[i], output.c_values[i], output.factors[i]);
+            let scale = final_product / c;
+            (scale * (a + b), scale * a.abs_diff(b), f | ((scale != 1) as u32 * TOP_BIT))
+        }).collect::<Vec<_>>();
+
+        output.into_magic_triples(final_product);
+        assert_eq!((0..output.len()).map(|i| (output.a_values[i], output.b_values[i], output.factors[i])).collect::<Vec<_>>(), expected);
+    }
+
+    // cargo test --release -- --ignored --nocapture it_benchmarks
+    #[test]
+    #[ignore]
+    fn it_benchmarks_the_triple_products() {
+        let mut triples = PythagoreanTriples::new(100_000);
+        triples.factors = vec![1; triples.len()];
+        let mut output = PythagoreanTriples::default();
+
+        let start = std::time::Instant::now();
+        for _ in 0..100 {
+            output.clear();
+            triples.product((5, 12, 13, 2), &mut output);
+            output.into_magic_triples(u64::MAX / 2);
+        }
+
+        println!("{} lanes: {:?} per 100,000 triples", crate::SIMD_LANES, start.elapsed() / 100);
+    }
+
     #[test]
     fn it_can_return_the_index_of_the_first_primitive_triple() {
         let mut triples0 = PythagoreanTriples::with_capacity(2);
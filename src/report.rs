@@ -0,0 +1,383 @@
+use crate::{Checkpoint, KnownSolutions, MagicSquareCandidate, NearMisses, Verdict, verify};
+use std::collections::HashSet;
+use std::io::Write;
+use std::ops::Range;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat { JsonLines, Box }
+
+// Writes candidates from all threads to a single output, one per line for
+// JSON Lines. Each candidate is flushed immediately since they are rare and
+// we don't want to lose them if the search is stopped.
+//
+// Every candidate is verified first. Invalid ones point to a bug in a pattern
+// checker so they go to stderr instead of the output, and only the verifier
+// can tag a candidate as a full solution.
+//
+// Candidates are turned into their canonical orientation and each square is
+// only reported once per center, even if several checkers or several passes of
+// one checker find it in different orientations. A square whose primitive cells
+// were already reported at another center is a scaled copy of it, so it is
+// hidden unless scaled copies are shown, in which case it is tagged. Squares in
+// the catalogue of known solutions are tagged with where they come from.
+//
+// The centers of a range aren't checked in increasing order so a scaled copy
+// can turn up before its primitive square. A square whose cells have a common
+// factor is written straight away if its primitive was already reported or if
+// it is a full solution. Otherwise it is held back until the range finishes
+// and then compared with the primitives from smallest center to largest. Both
+// the primitives and the held back squares are saved in each checkpoint.
+//
+// With a near-miss leaderboard, near misses are held back and only the best of
+// them are written when each search range finishes. Full solutions are still
+// written as soon as they are found. The board is saved in each checkpoint and
+// restored when the search resumes.
+pub struct Reporter {
+    output: Mutex<Box<dyn Write + Send>>,
+    format: OutputFormat,
+    near_misses: Option<NearMisses>,
+    seen: Mutex<HashSet<(u64, [u128; 9])>>,
+    primitives: Mutex<HashSet<[u128; 9]>>,
+    scaled: Mutex<Vec<MagicSquareCandidate>>,
+    show_scaled_copies: bool,
+    known_solutions: KnownSolutions,
+}
+
+impl Reporter {
+    pub fn new(output: Box<dyn Write + Send>, format: OutputFormat) -> Self {
+        Self { output: Mutex::new(output), format, near_misses: None, seen: Mutex::new(HashSet::new()), primitives: Mutex::new(HashSet::new()), scaled: Mutex::new(vec![]), show_scaled_copies: false, known_solutions: KnownSolutions::default() }
+    }
+
+    pub fn with_near_misses(output: Box<dyn Write + Send>, format: OutputFormat, near_misses: NearMisses) -> Self {
+        Self { near_misses: Some(near_misses), ..Self::new(output, format) }
+    }
+
+    pub fn showing_scaled_copies(self) -> Self {
+        Self { show_scaled_copies: true, ..self }
+    }
+
+    pub fn with_known_solutions(self, known_solutions: KnownSolutions) -> Self {
+        Self { known_solutions, ..self }
+    }
+
+    pub fn report(&self, mut candidate: MagicSquareCandidate) {
+        candidate.canonicalize();
+        candidate.verdict = verify(&candidate);
+
+        if candidate.verdict == Verdict::Invalid {
+            eprintln!("Discarded an invalid candidate from the pattern {} checker: {}", candidate.pattern.number(), candidate.to_json());
+            return;
+        }
+
+        if !self.seen.lock().unwrap().insert((candidate.center, candidate.cells)) { return; }
+
+        let mut primitives = self.primitives.lock().unwrap();
+        candidate.scaled_copy = candidate.scale > 1 && primitives.contains(&candidate.primitive_cells());
+
+        if candidate.scale > 1 && !candidate.scaled_copy && candidate.verdict != Verdict::VerifiedMagic {
+            drop(primitives);
+            self.scaled.lock().unwrap().push(candidate);
+            return;
+        }
+
+        primitives.insert(candidate.primitive_cells());
+        drop(primitives);
+        if !candidate.scaled_copy || self.show_scaled_copies { self.publish(candidate); }
+    }
+
+    fn publish(&self, mut candidate: MagicSquareCandidate) {
+        candidate.known = self.known_solutions.find(&candidate).map(String::from);
+
+        match candidate.verdict {
+            Verdict::VerifiedMagic => eprintln!("Found a verified magic square of squares: {}", candidate.to_json()),
+            Verdict::VerifiedSemiMagic | Verdict::Invalid => {},
+            Verdict::NearMiss | Verdict::Unverified => if let Some(near_misses) = &self.near_misses { near_misses.record(candidate); return; },
+        }
+
+        self.write(|format| match format {
+            OutputFormat::JsonLines => format!("{}\n", candidate.to_json()),
+            OutputFormat::Box => format!("{candidate}\n"),
+        });
+    }
+
+    // Each center is only in one range so the squares seen so far can be
+    // forgotten once it finishes.
+    pub fn finish_range(&self, range: &Range<u64>) {
+        let mut scaled = std::mem::take(&mut *self.scaled.lock().unwrap());
+        scaled.sort_by_key(|candidate| (candidate.center, candidate.cells));
+
+        for mut candidate in scaled {
+            candidate.scaled_copy = !self.primitives.lock().unwrap().insert(candidate.primitive_cells());
+            if !candidate.scaled_copy || self.show_scaled_copies { self.publish(candidate); }
+        }
+
+        self.seen.lock().unwrap().clear();
+        let Some(near_misses) = &self.near_misses else { return };
+        let entries = near_misses.take();
+        eprintln!("Writing the {} closest near misses in the range {range:?}.", entries.len());
+
+        for (rank, entry) in entries.iter().enumerate().map(|(i, entry)| (i + 1, entry)) {
+            self.write(|format| match format {
+                OutputFormat::JsonLines => format!("{}\n", entry.to_json(range, rank)),
+                OutputFormat::Box => format!("Near miss {rank} in the range {range:?} with {} squares (score {}):\n{}\n", entry.num_squares, entry.score, entry.candidate),
+            });
+        }
+    }
+
+    pub fn save(&self, checkpoint: &mut Checkpoint) {
+        if let Some(near_misses) = &self.near_misses { checkpoint.near_misses = near_misses.candidates(); }
+        checkpoint.primitives = self.primitives.lock().unwrap().iter().copied().collect();
+        checkpoint.held_back = self.scaled.lock().unwrap().clone();
+    }
+
+    // Only the cells are saved so the verdicts and notes are worked out again.
+    pub fn restore(&self, checkpoint: &Checkpoint) {
+        self.primitives.lock().unwrap().extend(checkpoint.primitives.iter().copied());
+        self.scaled.lock().unwrap().extend(checkpoint.held_back.iter().map(|candidate| MagicSquareCandidate { verdict: verify(candidate), ..candidate.clone() }));
+
+        let Some(near_misses) = &self.near_misses else { return };
+
+        for candidate in &checkpoint.near_misses {
+            let mut candidate = candidate.clone();
+            candidate.verdict = verify(&candidate);
+            candidate.known = self.known_solutions.find(&candidate).map(String::from);
+            near_misses.record(candidate);
+        }
+    }
+
+    fn write(&self, line: impl FnOnce(OutputFormat) -> String) {
+        let mut output = self.output.lock().unwrap();
+        output.write_all(line(self.format).as_bytes()).and_then(|_| output.flush()).expect("Failed to write the candidate");
+    }
+}
+
+// An output for tests that can still be read after it's given to a Reporter.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct SharedBuffer(pub std::sync::Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(bytes) }
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CompositeNumber, Pattern, PythagoreanTriples, TripleOrder, SALLOWS};
+    use crate::classify::classify;
+
+    #[test]
+    fn it_writes_one_json_object_per_line() {
+        let buffer = SharedBuffer::default();
+        let reporter = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines);
+
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+        reporter.report(MagicSquareCandidate::new(Pattern::Unclassified, 5, [22, 38, 15, 18, 25, 32, 35, 12, 28]));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"pattern":4,"center":425,"#));
+        assert!(lines[0].ends_with(r#""verdict":"near_miss"}"#));
+    }
+
+    #[test]
+    fn it_writes_each_square_once_in_its_canonical_orientation() {
+        let buffer = SharedBuffer::default();
+        let reporter = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines);
+
+        let mut reflected = SALLOWS;
+        for row in reflected.chunks_mut(3) { row.reverse(); }
+
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, reflected));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(r#""cells":[42025,277729,222121,360721,180625,529,139129,83521,319225]"#));
+    }
+
+    #[test]
+    fn it_hides_or_tags_scaled_copies_of_squares_already_reported() {
+        let lines = |reporter: Reporter, buffer: SharedBuffer| {
+            reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+            reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 1275, SALLOWS.map(|cell| cell * 9)));
+            reporter.finish_range(&(0..1000));
+            reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 2125, SALLOWS.map(|cell| cell * 25)));
+            reporter.finish_range(&(1000..3000));
+
+            let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            output.lines().map(String::from).collect::<Vec<_>>()
+        };
+
+        let buffer = SharedBuffer::default();
+        let hidden = lines(Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines), buffer);
+        assert_eq!(hidden.len(), 1);
+        assert!(hidden[0].contains(r#""scale":1,"scaled_copy":false"#));
+
+        let buffer = SharedBuffer::default();
+        let tagged = lines(Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines).showing_scaled_copies(), buffer);
+        assert_eq!(tagged.len(), 3);
+        assert!(tagged[1].contains(r#""center":1275,"#) && tagged[1].contains(r#""scale":9,"scaled_copy":true"#));
+        assert!(tagged[2].contains(r#""center":2125,"#) && tagged[2].contains(r#""scale":25,"scaled_copy":true"#));
+    }
+
+    #[test]
+    fn it_hides_scaled_copies_found_before_their_primitive_square() {
+        let buffer = SharedBuffer::default();
+        let reporter = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines);
+
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 2125, SALLOWS.map(|cell| cell * 25)));
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+        reporter.finish_range(&(0..3000));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(r#""center":425,"#));
+    }
+
+    #[test]
+    fn it_only_holds_back_scaled_squares_whose_primitive_has_not_been_reported() {
+        let buffer = SharedBuffer::default();
+        let reporter = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines).showing_scaled_copies();
+        let lines = || String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap().lines().map(String::from).collect::<Vec<_>>();
+
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 2125, SALLOWS.map(|cell| cell * 25)));
+        assert_eq!(lines().len(), 2);
+        assert!(lines()[1].contains(r#""scale":25,"scaled_copy":true"#));
+
+        reporter.report(MagicSquareCandidate::new(Pattern::Unclassified, 10, [22, 38, 15, 18, 25, 32, 35, 12, 28].map(|cell| cell * 4)));
+        assert_eq!(lines().len(), 2);
+
+        reporter.finish_range(&(0..3000));
+        assert_eq!(lines().len(), 3);
+    }
+
+    #[test]
+    fn it_restores_the_primitives_and_held_back_squares_from_a_checkpoint() {
+        let reporter = Reporter::new(Box::new(SharedBuffer::default()), OutputFormat::JsonLines);
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+        reporter.report(MagicSquareCandidate::new(Pattern::Unclassified, 10, [22, 38, 15, 18, 25, 32, 35, 12, 28].map(|cell| cell * 4)));
+
+        let mut checkpoint = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(10)).checkpoint();
+        reporter.save(&mut checkpoint);
+        assert_eq!((checkpoint.primitives.len(), checkpoint.held_back.len()), (1, 1));
+
+        let buffer = SharedBuffer::default();
+        let resumed = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines);
+        resumed.restore(&checkpoint);
+        resumed.report(MagicSquareCandidate::new(Pattern::Pattern4, 2125, SALLOWS.map(|cell| cell * 25)));
+        resumed.finish_range(&(0..3000));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(r#""center":10,"#) && lines[0].contains(r#""scale":4,"scaled_copy":false"#));
+    }
+
+    #[test]
+    fn it_tags_known_solutions_with_where_they_come_from() {
+        let buffer = SharedBuffer::default();
+        let reporter = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines).with_known_solutions(KnownSolutions::builtin());
+
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+        reporter.report(MagicSquareCandidate::new(Pattern::Unclassified, 5, [22, 38, 15, 18, 25, 32, 35, 12, 28]));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert!(lines[0].contains(r#""known":"Lee Sallows'"#));
+        assert!(lines[1].contains(r#""known":null"#));
+    }
+
+    #[test]
+    fn it_tags_every_built_in_known_solution_when_it_is_found() {
+        let known_solutions = KnownSolutions::builtin();
+        assert!(known_solutions.entries().count() > 0);
+
+        // Entries that are only magic along their rows and columns are reported
+        // as semi-magic squares.
+        for (&cells, note) in known_solutions.entries() {
+            let classification = classify(cells).unwrap();
+            let is_magic = classification.is_magic();
+            let mut candidate = classification.candidate;
+
+            if !is_magic {
+                candidate = MagicSquareCandidate::new(Pattern::SemiMagic, candidate.center, candidate.cells);
+                candidate.verdict = verify(&candidate);
+            }
+
+            assert!(candidate.squares.iter().filter(|&&s| s).count() >= 7, "{note}");
+            assert!(matches!(candidate.verdict, Verdict::VerifiedMagic | Verdict::NearMiss | Verdict::VerifiedSemiMagic), "{note}");
+
+            let buffer = SharedBuffer::default();
+            let reporter = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines).with_known_solutions(KnownSolutions::builtin());
+            reporter.report(candidate);
+
+            let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            assert!(output.contains(&format!(r#""known":"{note}""#)), "{note}");
+        }
+    }
+
+    #[test]
+    fn it_does_not_write_invalid_candidates() {
+        let buffer = SharedBuffer::default();
+        let reporter = Reporter::new(Box::new(buffer.clone()), OutputFormat::JsonLines);
+
+        let mut cells = SALLOWS;
+        cells[0] += 1;
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, cells));
+
+        assert!(buffer.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_writes_the_near_miss_leaderboard_when_a_range_finishes() {
+        let buffer = SharedBuffer::default();
+        let reporter = Reporter::with_near_misses(Box::new(buffer.clone()), OutputFormat::JsonLines, NearMisses::new(10, 7));
+
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+        assert!(buffer.0.lock().unwrap().is_empty());
+
+        reporter.finish_range(&(0..1000));
+        reporter.finish_range(&(1000..2000));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with(r#"{"range":[0,1000],"rank":1,"squares":7,"#));
+    }
+
+    #[test]
+    fn it_restores_the_near_miss_leaderboard_from_a_checkpoint() {
+        let reporter = Reporter::with_near_misses(Box::new(SharedBuffer::default()), OutputFormat::JsonLines, NearMisses::new(10, 7));
+        reporter.report(MagicSquareCandidate::new(Pattern::Pattern4, 425, SALLOWS));
+
+        let mut checkpoint = CompositeNumber::new(2..=3, 0..1000, vec![TripleOrder::ByPrimitiveAndA], false, PythagoreanTriples::new(10)).checkpoint();
+        reporter.save(&mut checkpoint);
+        assert_eq!(checkpoint.near_misses.len(), 1);
+
+        let buffer = SharedBuffer::default();
+        let resumed = Reporter::with_near_misses(Box::new(buffer.clone()), OutputFormat::JsonLines, NearMisses::new(10, 7)).with_known_solutions(KnownSolutions::builtin());
+        resumed.restore(&checkpoint);
+        resumed.finish_range(&(0..1000));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with(r#"{"range":[0,1000],"rank":1,"squares":7,"#));
+        assert!(lines[0].contains(r#""known":"Lee Sallows'"#) && lines[0].contains(r#""verdict":"near_miss""#));
+    }
+}
@@ -0,0 +1,124 @@
+use rayon::ScopeFifo;
+use std::sync::{Condvar, Mutex};
+
+// Feeds tasks to the thread pool as they are enumerated rather than in fixed
+// batches that each wait for their slowest task. Tasks are queued in windows
+// and each window is sorted by its estimated cost so the most expensive tasks
+// start first and the cheap ones fill in the gaps at the end.
+//
+// A new window is only handed to the pool once at most one window's worth of
+// tasks is still running, which keeps the threads busy without enumerating
+// arbitrarily far ahead of them.
+pub struct Scheduler<T, F> {
+    window: usize,
+    run: F,
+    pending: Mutex<Vec<(u64, T)>>,
+    in_flight: Mutex<usize>,
+    finished: Condvar,
+}
+
+// Counts the task as finished even if it panics so that nothing waits on it
+// forever. The panic is then raised again at the end of the scope.
+struct Finished<'a>(&'a Mutex<usize>, &'a Condvar);
+
+impl<T: Send, F: Fn(T) + Sync> Scheduler<T, F> {
+    pub fn new(window: usize, run: F) -> Self {
+        Self { window, run, pending: Mutex::new(Vec::with_capacity(window)), in_flight: Mutex::new(0), finished: Condvar::new() }
+    }
+
+    pub fn push<'s>(&'s self, scope: &ScopeFifo<'s>, cost: u64, task: T) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.push((cost, task));
+
+        if pending.len() >= self.window {
+            drop(pending);
+            self.flush(scope);
+        }
+    }
+
+    // Hand every queued task to the pool, most expensive first.
+    pub fn flush<'s>(&'s self, scope: &ScopeFifo<'s>) {
+        let mut tasks = std::mem::take(&mut *self.pending.lock().unwrap());
+        if tasks.is_empty() { return; }
+
+        tasks.sort_by_key(|&(cost, _)| std::cmp::Reverse(cost));
+        self.wait_for_at_most(self.window);
+        *self.in_flight.lock().unwrap() += tasks.len();
+
+        for (_, task) in tasks {
+            scope.spawn_fifo(move |_| {
+                let _finished = Finished(&self.in_flight, &self.finished);
+                (self.run)(task);
+            });
+        }
+    }
+
+    // Wait for every task handed to the pool so far. This is the only barrier
+    // and is used to take a consistent checkpoint.
+    pub fn wait_until_idle<'s>(&'s self, scope: &ScopeFifo<'s>) {
+        self.flush(scope);
+        self.wait_for_at_most(0);
+    }
+
+    // The waiting thread must not be one of the pool's own threads because it
+    // blocks rather than running tasks while it waits.
+    fn wait_for_at_most(&self, num_tasks: usize) {
+        let in_flight = self.in_flight.lock().unwrap();
+        drop(self.finished.wait_while(in_flight, |in_flight| *in_flight > num_tasks).unwrap());
+    }
+}
+
+impl Drop for Finished<'_> {
+    fn drop(&mut self) {
+        *self.0.lock().unwrap() -= 1;
+        self.1.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rayon::ThreadPoolBuilder;
+
+    #[test]
+    fn it_runs_every_task_once() {
+        let ran = Mutex::new(vec![]);
+        let scheduler = Scheduler::new(7, |task: usize| ran.lock().unwrap().push(task));
+
+        rayon::in_place_scope_fifo(|scope| {
+            for task in 0..100 { scheduler.push(scope, task as u64 % 3, task); }
+            scheduler.flush(scope);
+        });
+
+        let mut ran = ran.into_inner().unwrap();
+        ran.sort();
+        assert_eq!(ran, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_starts_the_most_expensive_tasks_of_a_window_first() {
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let ran = Mutex::new(vec![]);
+        let scheduler = Scheduler::new(4, |cost: u64| ran.lock().unwrap().push(cost));
+
+        pool.in_place_scope_fifo(|scope| {
+            for cost in [3, 9, 1, 4, 2, 8, 5] { scheduler.push(scope, cost, cost); }
+            scheduler.flush(scope);
+        });
+
+        assert_eq!(ran.into_inner().unwrap(), &[9, 4, 3, 1, 8, 5, 2]);
+    }
+
+    #[test]
+    fn it_waits_for_every_task_before_a_checkpoint() {
+        let ran = Mutex::new(0);
+        let scheduler = Scheduler::new(1000, |_: ()| *ran.lock().unwrap() += 1);
+
+        rayon::in_place_scope_fifo(|scope| {
+            for _ in 0..100 { scheduler.push(scope, 1, ()); }
+            scheduler.wait_until_idle(scope);
+
+            assert_eq!(*ran.lock().unwrap(), 100);
+        });
+    }
+}
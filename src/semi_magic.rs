@@ -0,0 +1,119 @@
+use crate::{MagicSquareCandidate, Pattern, PatternChecker, MagicTriples, TripleOrder};
+use std::collections::HashSet;
+
+// Semi-magic squares of squares (rows and columns only) are known to exist so
+// this mode is a self test: it gives positive results on real data from the
+// same enumeration, magic triples, reporter and verifier as the main search.
+//
+// Each magic triple (x², c², y²) gives a Pythagorean triple u² + v² = c² with
+// u = (x + y) / 2 and v = (x - y) / 2, i.e. a rotation by a rational angle.
+// Composing three of them about the z, x and z axes (Euler angles) gives an
+// orthogonal matrix whose entries are integers over c³. Its rows and columns
+// have unit length so the squares of those integers form a semi-magic square
+// with the magic sum c⁶.
+//
+// The cells are at most c⁶ so the center has to be below 2^21 for them to fit
+// in a u128, and the search is only meant for small ranges.
+
+pub const MAX_SEMI_MAGIC_CENTER: u64 = (1 << 21) - 1;
+
+pub struct SemiMagicChecker;
+
+impl PatternChecker for SemiMagicChecker {
+    fn name(&self) -> &'static str { "semimagic" }
+    fn triple_order(&self) -> TripleOrder { TripleOrder::ByA }
+    fn min_triples(&self) -> usize { 1 }
+
+    fn check(&self, triples: &MagicTriples, report: &(dyn Fn(MagicSquareCandidate) + Sync)) {
+        check_semi_magic(triples.a_values, triples.b_values, triples.center, &report);
+    }
+}
+
+pub fn check_semi_magic(a_values: &[u64], b_values: &[u64], c: u64, report: &impl Fn(MagicSquareCandidate)) {
+    if c > MAX_SEMI_MAGIC_CENTER { return; }
+
+    let legs = a_values.iter().zip(b_values).flat_map(|(&x, &y)| {
+        let (u, v) = ((x + y) as i128 / 2, (x - y) as i128 / 2);
+        [(u, v), (v, u)]
+    }).collect::<Vec<_>>();
+
+    let c = c as i128;
+    let mut seen = HashSet::new();
+
+    for &(u1, v1) in &legs {
+        for &(u2, v2) in &legs {
+            for &(u3, v3) in &legs {
+                let rotation = multiply(multiply(z_rotation(u1, v1, c), x_rotation(u2, v2, c)), z_rotation(u3, v3, c));
+                let cells = rotation.map(|entry| (entry * entry) as u128);
+                if cells.contains(&0) { continue; }
+
+                let mut sorted = cells;
+                sorted.sort();
+                if sorted.windows(2).any(|pair| pair[0] == pair[1]) || !seen.insert(sorted) { continue; }
+
+                report(MagicSquareCandidate::new(Pattern::SemiMagic, c as u64, cells));
+            }
+        }
+    }
+}
+
+fn z_rotation(u: i128, v: i128, c: i128) -> [i128; 9] {
+    [u, -v, 0, v, u, 0, 0, 0, c]
+}
+
+fn x_rotation(u: i128, v: i128, c: i128) -> [i128; 9] {
+    [c, 0, 0, 0, u, -v, 0, v, u]
+}
+
+fn multiply(a: [i128; 9], b: [i128; 9]) -> [i128; 9] {
+    std::array::from_fn(|i| (0..3).map(|k| a[i / 3 * 3 + k] * b[k * 3 + i % 3]).sum())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{verify, CompositeNumber, PatternRegistry, PythagoreanTriples, SearchMode, Verdict};
+    use std::sync::Mutex;
+
+    fn hits(c: u64, a_values: &[u64], b_values: &[u64]) -> Vec<MagicSquareCandidate> {
+        let hits = Mutex::new(vec![]);
+        check_semi_magic(a_values, b_values, c, &|candidate| hits.lock().unwrap().push(candidate));
+        hits.into_inner().unwrap()
+    }
+
+    #[test]
+    fn it_builds_semi_magic_squares_of_squares_from_the_magic_triples() {
+        // The magic triples of 25 are (17², 25², 31²) and (5², 25², 35²), from
+        // the Pythagorean triples (24, 7, 25) and (20, 15, 25).
+        let hits = hits(25, &[31, 35], &[17, 5]);
+        assert!(!hits.is_empty());
+
+        for candidate in hits {
+            assert!(candidate.squares.iter().all(|&s| s));
+            assert_eq!(verify(&candidate), Verdict::VerifiedSemiMagic);
+
+            for i in 0..3 {
+                assert_eq!(candidate.cells[i * 3..i * 3 + 3].iter().sum::<u128>(), 25u128.pow(6));
+                assert_eq!((0..3).map(|j| candidate.cells[j * 3 + i]).sum::<u128>(), 25u128.pow(6));
+            }
+        }
+    }
+
+    #[test]
+    fn it_skips_centers_whose_entries_would_overflow() {
+        assert!(hits(MAX_SEMI_MAGIC_CENTER + 1, &[31], &[17]).is_empty());
+    }
+
+    #[test]
+    fn it_finds_verified_squares_through_the_whole_pipeline() {
+        let registry = PatternRegistry::for_modes(&[SearchMode::SemiMagic], 7);
+        let verdicts = Mutex::new(vec![]);
+
+        let mut composite_number = CompositeNumber::new(2..=3, 0..1000, registry.triple_orders(), false, PythagoreanTriples::new(50));
+        composite_number.for_each_in_search_range(|triples| registry.check_each(triples, &|candidate| verdicts.lock().unwrap().push(verify(&candidate))));
+
+        let verdicts = verdicts.into_inner().unwrap();
+        assert!(!verdicts.is_empty());
+        assert!(verdicts.iter().all(|&verdict| verdict == Verdict::VerifiedSemiMagic));
+    }
+}
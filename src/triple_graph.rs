@@ -0,0 +1,166 @@
+use crate::{MagicSquareCandidate, Pattern, PatternChecker, MagicTriples, TripleOrder, canonical_orientation};
+use std::collections::HashSet;
+
+// A search that doesn't assume any pattern. Each magic triple (x², c², y²) of
+// a center is an edge between two squares whose midpoint is e = c², and every
+// line through the center of a magic square with center e holds one such pair.
+// The whole grid is determined by the offsets x and y of its diagonals:
+//
+//   e - x      e + x + y  e - y
+//   e + x - y  e          e - x + y
+//   e + y      e - x - y  e + x
+//
+// The diagonal, anti-diagonal, middle column and middle row are square when x,
+// y, x + y and x - y are offsets ±(a² - e) of magic triples. A grid with seven
+// or more squares has at most two non-square cells, which leaves at least two
+// of those lines square, so every such grid is found by putting a triple on
+// each of two lines and testing the remaining cells.
+
+// The coefficients of x and y in the offset of each line through the center.
+const LINES: [(i128, i128); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+pub struct TripleGraphChecker {
+    pub min_squares: usize,
+}
+
+impl PatternChecker for TripleGraphChecker {
+    fn name(&self) -> &'static str { "graph" }
+    fn triple_order(&self) -> TripleOrder { TripleOrder::ByA }
+
+    // Each non-square cell other than the center breaks one of the four lines.
+    fn min_triples(&self) -> usize { self.min_squares.saturating_sub(5) }
+
+    fn check(&self, triples: &MagicTriples, report: &(dyn Fn(MagicSquareCandidate) + Sync)) {
+        check_triple_graph(triples.a_values, triples.center, self.min_squares, &report);
+    }
+}
+
+pub fn check_triple_graph(a_values: &[u64], c: u64, min_squares: usize, report: &impl Fn(MagicSquareCandidate)) {
+    let center_square = c as u128 * c as u128;
+    let offsets = a_values.iter().map(|&a| (a as u128 * a as u128 - center_square) as i128).collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+
+    for (i, &(x1, y1)) in LINES.iter().enumerate() {
+        for &(x2, y2) in &LINES[i + 1..] {
+            let determinant = x1 * y2 - y1 * x2;
+
+            // Negating x and y rotates the grid by 180 degrees, so the offset
+            // of the first line only needs to be positive.
+            for &offset1 in &offsets {
+                for offset2 in offsets.iter().flat_map(|&o| [o, -o]) {
+                    let Some(x) = divide_difference(offset1 * y2, y1 * offset2, determinant) else { continue };
+                    let Some(y) = divide_difference(x1 * offset2, x2 * offset1, determinant) else { continue };
+
+                    let Some(cells) = grid(center_square, x, y) else { continue };
+                    let candidate = MagicSquareCandidate::new(Pattern::Unclassified, c, cells);
+                    if candidate.squares.iter().filter(|&&s| s).count() < min_squares { continue; }
+
+                    let cells = canonical_orientation(cells);
+                    if !seen.insert(cells) { continue; }
+
+                    let mut candidate = MagicSquareCandidate::new(Pattern::Unclassified, c, cells);
+                    candidate.pattern = Pattern::classify(&candidate.squares).unwrap_or(Pattern::Unclassified);
+                    report(candidate);
+                }
+            }
+        }
+    }
+}
+
+// (p - q) / determinant if it is an integer, without forming p - q, which can
+// pass i128::MAX for centers above 2^63. None if the quotient overflows, but
+// then it is larger than any offset anyway.
+fn divide_difference(p: i128, q: i128, determinant: i128) -> Option<i128> {
+    let divisor = determinant.abs();
+    if p.rem_euclid(divisor) != q.rem_euclid(divisor) { return None; }
+
+    p.div_euclid(divisor).checked_sub(q.div_euclid(divisor)).map(|quotient| quotient * determinant.signum())
+}
+
+// The grid for the offsets x and y, if its cells are positive and distinct.
+// Each offset is smaller than e = c² < 2^127 but a cell can be almost 2e, so
+// the cells are only formed once they are known to fit in a u128.
+fn grid(center_square: u128, x: i128, y: i128) -> Option<[u128; 9]> {
+    let (sum, difference) = (x.checked_add(y)?, x.checked_sub(y)?);
+    if [x, y, sum, difference].iter().any(|offset| offset.unsigned_abs() >= center_square) { return None; }
+
+    let offsets = [-x, sum, -y, difference, 0, -difference, y, -sum, x];
+    let cells = offsets.map(|offset| center_square.strict_add_signed(offset));
+
+    let mut sorted = cells;
+    sorted.sort();
+    if sorted.windows(2).any(|pair| pair[0] == pair[1]) { return None; }
+
+    Some(cells)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CompositeNumber, PatternRegistry, PythagoreanTriples, SearchMode, verify, Verdict, MAX_CENTER, SALLOWS};
+    use std::sync::Mutex;
+
+    fn hits(search_modes: &[SearchMode], center_range: std::ops::Range<u64>) -> Vec<(u64, Pattern, [u128; 9])> {
+        let registry = PatternRegistry::for_modes(search_modes, 7);
+        let hits = Mutex::new(vec![]);
+
+        let mut composite_number = CompositeNumber::new(2..=4, center_range, registry.triple_orders(), false, PythagoreanTriples::new(200));
+        composite_number.for_each_in_search_range(|triples| {
+            registry.check_each(triples, &|candidate| hits.lock().unwrap().push((candidate.center, candidate.pattern, canonical_orientation(candidate.cells))));
+        });
+
+        let mut hits = hits.into_inner().unwrap();
+        hits.sort();
+        hits.dedup();
+        hits
+    }
+
+    #[test]
+    fn it_finds_sallows_square_as_pattern_4() {
+        let c: u64 = 425;
+        let twice_center_square = 2 * c * c;
+        let a_values = (c + 1..twice_center_square.isqrt()).filter(|a| (twice_center_square - a * a).isqrt().pow(2) == twice_center_square - a * a).collect::<Vec<_>>();
+
+        let found = Mutex::new(vec![]);
+        check_triple_graph(&a_values, c, 7, &|candidate| found.lock().unwrap().push(candidate));
+
+        let found = found.into_inner().unwrap();
+        assert!(found.iter().any(|candidate| candidate.pattern == Pattern::Pattern4 && candidate.cells == canonical_orientation(SALLOWS)));
+
+        for candidate in found {
+            assert!(candidate.squares.iter().filter(|&&s| s).count() >= 7);
+            assert_eq!(verify(&candidate), Verdict::NearMiss);
+        }
+    }
+
+    #[test]
+    fn it_divides_differences_that_do_not_fit_in_an_i128() {
+        let large = i128::MAX - 1;
+
+        assert_eq!(divide_difference(large, -large, -2), Some(-large));
+        assert_eq!(divide_difference(large, -large - 1, 2), None);
+        assert_eq!(divide_difference(large, -large, 1), None);
+        assert_eq!(divide_difference(7, 3, -2), Some(-2));
+    }
+
+    #[test]
+    fn it_forms_grids_with_cells_above_i128_max() {
+        let center_square = MAX_CENTER as u128 * MAX_CENTER as u128;
+        let half = center_square as i128 / 2;
+        assert!(grid(center_square, half, half + 1).is_none());
+
+        let cells = grid(center_square, half, half - 1).unwrap();
+        assert_eq!(cells[1], center_square + (2 * half - 1) as u128);
+        assert!(cells[1] > i128::MAX as u128);
+    }
+
+    #[test]
+    fn it_finds_every_grid_that_the_hand_written_checkers_find() {
+        let graph = hits(&[SearchMode::Graph], 0..30_000);
+        let patterns = hits(&[SearchMode::Patterns16, SearchMode::Patterns234, SearchMode::Patterns5], 0..30_000);
+
+        assert!(!patterns.is_empty());
+        assert!(patterns.iter().all(|hit| graph.binary_search(hit).is_ok()));
+        assert!(graph.iter().all(|&(_, pattern, _)| pattern != Pattern::Unclassified));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::PythagoreanTriples;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+// The table of primitive Pythagorean triples for the first n Pythagorean primes.
+// It is either computed in memory or memory-mapped from a cache file written by
+// the triples command so that several search processes share a single copy.
+//
+// The cache file is a 32 byte header followed by the a, b and c values, each
+// as an array of little-endian u64s:
+//
+//   magic (8 bytes) | version (u64) | num_triples (u64) | checksum (u64)
+
+pub enum TriplesTable {
+    Computed(PythagoreanTriples),
+    Mapped(MappedTriples),
+}
+
+pub struct MappedTriples {
+    mmap: Mmap,
+    len: usize,
+}
+
+const MAGIC: &[u8; 8] = b"MSOSPTT\0";
+const VERSION: u64 = 1;
+const HEADER_LEN: usize = 32;
+const CHECKSUM_CHUNK_LEN: usize = 1 << 20;
+
+impl TriplesTable {
+    pub fn a_values(&self) -> &[u64] {
+        match self {
+            TriplesTable::Computed(triples) => &triples.a_values,
+            TriplesTable::Mapped(mapped) => mapped.column(0),
+        }
+    }
+
+    pub fn b_values(&self) -> &[u64] {
+        match self {
+            TriplesTable::Computed(triples) => &triples.b_values,
+            TriplesTable::Mapped(mapped) => mapped.column(1),
+        }
+    }
+
+    pub fn c_values(&self) -> &[u64] {
+        match self {
+            TriplesTable::Computed(triples) => &triples.c_values,
+            TriplesTable::Mapped(mapped) => mapped.column(2),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.c_values().len()
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let temporary_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+        let columns = [self.a_values(), self.b_values(), self.c_values()];
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.len() as u64).to_le_bytes())?;
+        writer.write_all(&checksum(&columns).to_le_bytes())?;
+
+        for column in columns {
+            for value in column { writer.write_all(&value.to_le_bytes())?; }
+        }
+
+        writer.into_inner().map_err(|error| error.into_error())?.sync_all()?;
+        fs::rename(&temporary_path, path)
+    }
+
+    // Map the cache file into memory and check its checksum. If num_triples is
+    // given, only that many triples are used and the file must have at least
+    // that many.
+    pub fn read(path: &Path, num_triples: Option<usize>) -> io::Result<Self> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, format!("{}: {message}", path.display()));
+
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if cfg!(target_endian = "big") { return Err(invalid("the cache format is little-endian only".to_string())); }
+        if mmap.len() < HEADER_LEN || &mmap[0..8] != MAGIC { return Err(invalid("not a Pythagorean triples cache file".to_string())); }
+
+        let header_value = |i: usize| u64::from_le_bytes(mmap[i * 8..i * 8 + 8].try_into().unwrap());
+        let (version, file_len, expected_checksum) = (header_value(1), header_value(2), header_value(3));
+
+        if version != VERSION { return Err(invalid(format!("expected version {VERSION} but the file has version {version}"))); }
+
+        // The header isn't covered by the checksum so its length can't be trusted.
+        let expected_bytes = usize::try_from(file_len).ok().and_then(|len| len.checked_mul(24)).and_then(|bytes| bytes.checked_add(HEADER_LEN));
+        let Some(expected_bytes) = expected_bytes else { return Err(invalid(format!("the header claims {file_len} triples, which is too many to address"))) };
+        if mmap.len() != expected_bytes { return Err(invalid(format!("expected {expected_bytes} bytes for {file_len} triples but the file has {}", mmap.len()))); }
+        let file_len = file_len as usize;
+
+        let mapped = MappedTriples { mmap, len: file_len };
+        if checksum(&[mapped.column(0), mapped.column(1), mapped.column(2)]) != expected_checksum { return Err(invalid("the checksum does not match".to_string())); }
+
+        let len = num_triples.unwrap_or(file_len);
+        if len > file_len { return Err(invalid(format!("{len} triples were requested but the file only has {file_len}"))); }
+
+        Ok(TriplesTable::Mapped(MappedTriples { len, ..mapped }))
+    }
+}
+
+impl MappedTriples {
+    // The header is a multiple of 8 bytes and the mapping is page aligned so
+    // each column is correctly aligned for u64s.
+    fn column(&self, index: usize) -> &[u64] {
+        let file_len = (self.mmap.len() - HEADER_LEN) / 24;
+        let start = HEADER_LEN + index * file_len * 8;
+
+        unsafe { std::slice::from_raw_parts(self.mmap[start..].as_ptr() as *const u64, self.len) }
+    }
+}
+
+impl From<PythagoreanTriples> for TriplesTable {
+    fn from(triples: PythagoreanTriples) -> Self {
+        TriplesTable::Computed(triples)
+    }
+}
+
+// FNV-1a over whole words, hashed in parallel chunks and then combined in
+// order. It only needs to detect truncated or corrupted files.
+fn checksum(columns: &[&[u64]]) -> u64 {
+    let fnv = |hash: u64, word: u64| (hash ^ word).wrapping_mul(0x100_0000_01b3);
+    let offset_basis = 0xcbf2_9ce4_8422_2325;
+
+    columns.iter().fold(offset_basis, |hash, column| {
+        let chunk_hashes = column.par_chunks(CHECKSUM_CHUNK_LEN).map(|chunk| chunk.iter().fold(offset_basis, |h, &w| fnv(h, w))).collect::<Vec<_>>();
+        chunk_hashes.into_iter().fold(fnv(hash, column.len() as u64), fnv)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temporary_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("magic_square_of_squares_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn it_can_round_trip_the_table_through_a_cache_file() {
+        let path = temporary_path("round_trip.triples");
+        let computed = TriplesTable::from(PythagoreanTriples::new(100));
+        computed.write(&path).unwrap();
+
+        let mapped = TriplesTable::read(&path, None).unwrap();
+        assert!(matches!(mapped, TriplesTable::Mapped(_)));
+        assert_eq!(mapped.a_values(), computed.a_values());
+        assert_eq!(mapped.b_values(), computed.b_values());
+        assert_eq!(mapped.c_values(), computed.c_values());
+
+        let prefix = TriplesTable::read(&path, Some(10)).unwrap();
+        assert_eq!(prefix.c_values(), &computed.c_values()[..10]);
+        assert!(TriplesTable::read(&path, Some(101)).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_corrupted_cache_files() {
+        let path = temporary_path("corrupted.triples");
+        TriplesTable::from(PythagoreanTriples::new(100)).write(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_LEN + 100] ^= 1;
+        fs::write(&path, &corrupted).unwrap();
+        assert!(TriplesTable::read(&path, None).is_err());
+
+        fs::write(&path, &bytes[..bytes.len() - 8]).unwrap();
+        assert!(TriplesTable::read(&path, None).is_err());
+
+        let mut too_long = bytes.clone();
+        too_long[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&path, &too_long).unwrap();
+        assert!(TriplesTable::read(&path, None).is_err_and(|error| error.kind() == ErrorKind::InvalidData));
+
+        let mut wrapping = bytes.clone();
+        wrapping[16..24].copy_from_slice(&(u64::MAX / 24 + 1 + 100).to_le_bytes());
+        fs::write(&path, &wrapping).unwrap();
+        assert!(TriplesTable::read(&path, None).is_err_and(|error| error.kind() == ErrorKind::InvalidData));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[8] = 2;
+        fs::write(&path, &wrong_version).unwrap();
+        assert!(TriplesTable::read(&path, None).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{MagicSquareCandidate, Pattern};
+
+// Checks a candidate from scratch without trusting the pattern checker that
+// produced it. Cells are u128 so the sum of a line can exceed u128::MAX. Sums
+// are therefore accumulated into a (high, low) pair which is exact for any
+// three cells, and squareness uses a checked multiplication of the root.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict { Unverified, VerifiedMagic, VerifiedSemiMagic, NearMiss, Invalid }
+
+const LINES: [[usize; 3]; 8] = [
+    [0, 1, 2], [3, 4, 5], [6, 7, 8], // rows
+    [0, 3, 6], [1, 4, 7], [2, 5, 8], // columns
+    [0, 4, 8], [2, 4, 6],            // diagonals
+];
+
+impl Verdict {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Verdict::Unverified => "unverified",
+            Verdict::VerifiedMagic => "verified_magic",
+            Verdict::VerifiedSemiMagic => "verified_semi_magic",
+            Verdict::NearMiss => "near_miss",
+            Verdict::Invalid => "invalid",
+        }
+    }
+}
+
+pub fn verify(candidate: &MagicSquareCandidate) -> Verdict {
+    if candidate.pattern == Pattern::SemiMagic { return verify_semi_magic(candidate); }
+
+    let center = candidate.center as u128;
+    let center_square = center * center;
+    let magic_sum = wide_sum(&[center_square, center_square, center_square]);
+    let cells = &candidate.cells;
+
+    if cells[4] != center_square { return Verdict::Invalid; }
+
+    // A zero marks a cell that the checker did not compute so lines that
+    // contain one can't be checked, but the grid can't be a full solution.
+    let is_complete = cells.iter().all(|&cell| cell != 0);
+
+    for line in LINES {
+        let values = line.map(|i| cells[i]);
+        if values.contains(&0) { continue; }
+        if wide_sum(&values) != magic_sum { return Verdict::Invalid; }
+    }
+
+    for (&cell, &claimed_square) in cells.iter().zip(&candidate.squares) {
+        if claimed_square != (cell != 0 && is_exact_square(cell)) { return Verdict::Invalid; }
+    }
+
+    if is_complete && is_distinct(cells) && candidate.squares.iter().all(|&s| s) {
+        Verdict::VerifiedMagic
+    } else {
+        Verdict::NearMiss
+    }
+}
+
+// Semi-magic squares only need their rows and columns to have the same sum,
+// which doesn't depend on the center.
+fn verify_semi_magic(candidate: &MagicSquareCandidate) -> Verdict {
+    let cells = &candidate.cells;
+    let magic_sum = wide_sum(&cells[0..3]);
+
+    for line in &LINES[..6] {
+        if wide_sum(&line.map(|i| cells[i])) != magic_sum { return Verdict::Invalid; }
+    }
+
+    for (&cell, &claimed_square) in cells.iter().zip(&candidate.squares) {
+        if claimed_square != (cell != 0 && is_exact_square(cell)) { return Verdict::Invalid; }
+    }
+
+    if !cells.contains(&0) && is_distinct(cells) && candidate.squares.iter().all(|&s| s) {
+        Verdict::VerifiedSemiMagic
+    } else {
+        Verdict::NearMiss
+    }
+}
+
+fn is_distinct(cells: &[u128; 9]) -> bool {
+    let mut sorted = *cells;
+    sorted.sort_unstable();
+    sorted.windows(2).all(|pair| pair[0] != pair[1])
+}
+
+fn wide_sum(values: &[u128]) -> (u128, u128) {
+    values.iter().fold((0, 0), |(high, low), &value| {
+        let (low, carry) = low.overflowing_add(value);
+        (high + carry as u128, low)
+    })
+}
+
+fn is_exact_square(n: u128) -> bool {
+    let root = n.isqrt();
+    root.checked_mul(root) == Some(n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Pattern;
+
+    fn candidate(center: u64, cells: [u128; 9]) -> MagicSquareCandidate {
+        MagicSquareCandidate::new(Pattern::Pattern4, center, cells)
+    }
+
+    #[test]
+    fn it_tags_a_magic_square_with_non_square_cells_as_a_near_miss() {
+        // Sallows' square with seven square cells.
+        let sallows = candidate(425, [373 * 373, 289 * 289, 565 * 565, 360721, 425 * 425, 23 * 23, 205 * 205, 527 * 527, 222121]);
+        assert_eq!(sallows.squares, [true, true, true, false, true, true, true, true, false]);
+        assert_eq!(verify(&sallows), Verdict::NearMiss);
+    }
+
+    #[test]
+    fn it_tags_a_grid_whose_lines_do_not_sum_to_the_magic_sum_as_invalid() {
+        let broken = candidate(425, [373 * 373, 289 * 289, 565 * 565, 360721, 425 * 425, 23 * 23, 205 * 205, 527 * 527, 222122]);
+        assert_eq!(verify(&broken), Verdict::Invalid);
+
+        let wrong_center = candidate(425, [373 * 373, 289 * 289, 565 * 565, 360721, 425 * 425, 23 * 23, 205 * 205, 527 * 527, 222121]);
+        assert_eq!(verify(&MagicSquareCandidate { center: 424, ..wrong_center }), Verdict::Invalid);
+    }
+
+    #[test]
+    fn it_tags_a_grid_that_misreports_its_square_cells_as_invalid() {
+        let mut sallows = candidate(425, [373 * 373, 289 * 289, 565 * 565, 360721, 425 * 425, 23 * 23, 205 * 205, 527 * 527, 222121]);
+        sallows.squares[3] = true;
+
+        assert_eq!(verify(&sallows), Verdict::Invalid);
+    }
+
+    #[test]
+    fn it_does_not_verify_a_grid_with_repeated_cells() {
+        let trivial = candidate(5, [25; 9]);
+        assert_eq!(verify(&trivial), Verdict::NearMiss);
+    }
+
+    #[test]
+    fn it_checks_incomplete_grids_on_the_lines_that_are_known() {
+        let partial = candidate(425, [373 * 373, 0, 565 * 565, 360721, 425 * 425, 23 * 23, 205 * 205, 527 * 527, 0]);
+        assert_eq!(verify(&partial), Verdict::NearMiss);
+
+        let partial = candidate(425, [373 * 373, 0, 565 * 565, 360720, 425 * 425, 23 * 23, 205 * 205, 527 * 527, 0]);
+        assert_eq!(verify(&partial), Verdict::Invalid);
+    }
+
+    #[test]
+    fn it_verifies_semi_magic_squares_on_their_rows_and_columns_only() {
+        let cells = [127 * 127, 46 * 46, 58 * 58, 2 * 2, 113 * 113, 94 * 94, 74 * 74, 82 * 82, 97 * 97];
+        let semi_magic = MagicSquareCandidate::new(Pattern::SemiMagic, 113, cells);
+        assert_eq!(verify(&semi_magic), Verdict::VerifiedSemiMagic);
+        assert_eq!(verify(&candidate(113, cells)), Verdict::Invalid);
+
+        let mut broken = cells;
+        broken.swap(0, 1);
+        assert_eq!(verify(&MagicSquareCandidate::new(Pattern::SemiMagic, 113, broken)), Verdict::Invalid);
+
+        let repeated = MagicSquareCandidate::new(Pattern::SemiMagic, 5, [25; 9]);
+        assert_eq!(verify(&repeated), Verdict::NearMiss);
+    }
+
+    #[test]
+    fn it_sums_lines_beyond_u128_without_overflowing() {
+        let center = u64::MAX;
+        let center_square = center as u128 * center as u128;
+        let cells = [center_square; 9];
+
+        assert_eq!(wide_sum(&[center_square; 3]), (2, center_square.wrapping_mul(3)));
+        assert_eq!(verify(&candidate(center, cells)), Verdict::NearMiss);
+    }
+}